@@ -2,6 +2,8 @@ use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::fmt::Write;
+use std::path::Path;
 
 fn main() -> Result<()> {
     // This tells cargo to rerun this script if something in /res/ changes.
@@ -12,7 +14,43 @@ fn main() -> Result<()> {
     copy_options.overwrite = true;
     let mut paths_to_copy = Vec::new();
     paths_to_copy.push("data/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
 
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some() {
+        write_embedded_assets(Path::new(&out_dir).join("embedded_assets.rs").as_path())?;
+    }
+
+    Ok(())
+}
+
+// Generates a table of every file below data/, sorted by path so it can be
+// binary searched, with the contents pulled in by include_bytes!
+fn write_embedded_assets(out_file: &Path) -> Result<()> {
+    let data_dir = Path::new(&env::var("CARGO_MANIFEST_DIR")?).join("data");
+    println!("cargo:rerun-if-changed={}", data_dir.display());
+
+    let mut files = Vec::new();
+    for entry in glob::glob(&format!("{}/**/*", data_dir.display()))? {
+        let path = entry?;
+        if !path.is_file() {
+            continue;
+        }
+        let key = path
+            .strip_prefix(&data_dir)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((key, path));
+    }
+    files.sort();
+
+    let mut table = String::from("pub static EMBEDDED_ASSETS: &[(&str, &[u8])] = &[\n");
+    for (key, path) in &files {
+        writeln!(table, "    ({:?}, include_bytes!({:?})),", key, path)?;
+    }
+    table.push_str("];\n");
+
+    std::fs::write(out_file, table)?;
     Ok(())
 }
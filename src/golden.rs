@@ -0,0 +1,293 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use iced_wgpu::wgpu;
+use image::{Rgba, RgbaImage};
+
+use crate::headless;
+use crate::state::render_components::skybox::Background;
+use crate::state::screenshot;
+use crate::state::state::{NoAdapterError, State};
+
+// A scene rendered headless with a pinned camera. Animation advances once per
+// frame, so frames fixes the point in time that is captured.
+pub struct GoldenScene {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub background: Background,
+    pub frames: u32,
+}
+
+// The scenes tests/golden.rs compares against tests/golden/<name>.png
+pub fn scenes() -> Vec<GoldenScene> {
+    vec![
+        GoldenScene {
+            name: "instances",
+            width: 320,
+            height: 240,
+            eye: [0.0, 16.0, 64.0],
+            target: [0.0, 0.0, 0.0],
+            background: Background::ClearColor,
+            frames: 1,
+        },
+        GoldenScene {
+            name: "instances_rotated",
+            width: 320,
+            height: 240,
+            eye: [0.0, 16.0, 64.0],
+            target: [0.0, 0.0, 0.0],
+            background: Background::ClearColor,
+            frames: 45,
+        },
+        GoldenScene {
+            name: "skybox",
+            width: 320,
+            height: 240,
+            eye: [24.0, 4.0, 24.0],
+            target: [0.0, 8.0, 0.0],
+            background: Background::Skybox,
+            frames: 1,
+        },
+    ]
+}
+
+// Renders the scene on whatever adapter is available, None if there is none
+pub fn render(scene: &GoldenScene) -> Result<Option<RgbaImage>> {
+    let mut state = match pollster::block_on(State::new_headless(scene.width, scene.height)) {
+        Ok(state) => state,
+        Err(e) if e.is::<NoAdapterError>() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    state.color_pass.clear_color = wgpu::Color::BLACK;
+    state.color_pass.background = scene.background;
+
+    state.finish_loading();
+    let failures = state.loader.status().failures;
+    if !failures.is_empty() {
+        bail!("Assets failed to load: {}", failures.join(", "));
+    }
+
+    // Loading a model frames it, so the camera is pinned afterwards
+    let camera = &mut state.color_pass.camera_resources.camera;
+    camera.eye = scene.eye.into();
+    camera.target = scene.target.into();
+    camera.znear = 0.1;
+    camera.zfar = 1000.0;
+
+    for _ in 0..scene.frames {
+        state.update();
+    }
+    pollster::block_on(headless::render_frame(&mut state)).map(Some)
+}
+
+// Renderers don't agree on every bit, so images only differ once enough pixels
+// differ visibly
+#[derive(Copy, Clone, Debug)]
+pub struct Tolerance {
+    // Perceptual difference from 0.0 to 1.0 a pixel may have and still match
+    pub pixel_threshold: f32,
+    // Fraction of the pixels allowed to exceed pixel_threshold
+    pub max_differing_fraction: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            pixel_threshold: 0.1,
+            max_differing_fraction: 0.001,
+        }
+    }
+}
+
+pub struct Comparison {
+    pub differing_pixels: usize,
+    pub total_pixels: usize,
+    // The reference faded out with the differing pixels in red
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn differing_fraction(&self) -> f32 {
+        self.differing_pixels as f32 / self.total_pixels.max(1) as f32
+    }
+
+    pub fn matches(&self, tolerance: &Tolerance) -> bool {
+        self.differing_fraction() <= tolerance.max_differing_fraction
+    }
+}
+
+pub fn compare(actual: &RgbaImage, reference: &RgbaImage, tolerance: &Tolerance) -> Result<Comparison> {
+    if actual.dimensions() != reference.dimensions() {
+        bail!("Size {:?} doesn't match the reference size {:?}", actual.dimensions(), reference.dimensions());
+    }
+
+    let mut differing_pixels = 0;
+    let diff = RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let expected = *reference.get_pixel(x, y);
+        if color_difference(*actual.get_pixel(x, y), expected) > tolerance.pixel_threshold {
+            differing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let faded = (255.0 - (255.0 - luma(expected)) * 0.1) as u8;
+            Rgba([faded, faded, faded, 255])
+        }
+    });
+
+    Ok(Comparison {
+        differing_pixels,
+        total_pixels: (reference.width() * reference.height()) as usize,
+        diff,
+    })
+}
+
+// Distance in YIQ space weighted like the eye's sensitivity, as in pixelmatch,
+// scaled so the largest possible difference is 1.0. Colors are blended onto
+// white first.
+fn color_difference(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    let yiq = |pixel: Rgba<u8>| {
+        let alpha = pixel[3] as f32 / 255.0;
+        let [r, g, b] = [0, 1, 2].map(|c| 255.0 + (pixel[c] as f32 - 255.0) * alpha);
+        [
+            r * 0.2988953 + g * 0.5866225 + b * 0.11448223,
+            r * 0.59597796 - g * 0.2741761 - b * 0.3218019,
+            r * 0.21147017 - g * 0.5226171 + b * 0.31114694,
+        ]
+    };
+    let (a, b) = (yiq(a), yiq(b));
+    let delta = 0.5053 * (a[0] - b[0]).powi(2) + 0.299 * (a[1] - b[1]).powi(2) + 0.1957 * (a[2] - b[2]).powi(2);
+    (delta / 35215.0).sqrt()
+}
+
+fn luma(pixel: Rgba<u8>) -> f32 {
+    pixel[0] as f32 * 0.299 + pixel[1] as f32 * 0.587 + pixel[2] as f32 * 0.114
+}
+
+pub enum Outcome {
+    // No adapter, nothing was rendered
+    Skipped,
+    Matched,
+    // UPDATE_GOLDEN=1 is set
+    Recorded(PathBuf),
+}
+
+// Renders the scene and compares it with reference_dir/<name>.png. A missing
+// reference is an error, UPDATE_GOLDEN=1 records all of them from the
+// rendering instead. On a mismatch the rendering and a diff image go to
+// output_dir.
+pub fn check(scene: &GoldenScene, reference_dir: &Path, output_dir: &Path, tolerance: &Tolerance) -> Result<Outcome> {
+    let actual = match render(scene)? {
+        Some(actual) => actual,
+        None => return Ok(Outcome::Skipped),
+    };
+
+    let reference_path = reference_dir.join(format!("{}.png", scene.name));
+    let update = matches!(std::env::var("UPDATE_GOLDEN").as_deref(), Ok("1") | Ok("true"));
+    if update {
+        screenshot::save_png(&actual, &reference_path)?;
+        return Ok(Outcome::Recorded(reference_path));
+    }
+    if !reference_path.is_file() {
+        bail!("Missing reference {:?}, run with UPDATE_GOLDEN=1 to record it", reference_path);
+    }
+
+    let reference = image::open(&reference_path)
+        .with_context(|| format!("Failed to read {:?}", reference_path))?
+        .to_rgba8();
+    let comparison = compare(&actual, &reference, tolerance)?;
+    if comparison.matches(tolerance) {
+        return Ok(Outcome::Matched);
+    }
+
+    let actual_path = output_dir.join(format!("{}.actual.png", scene.name));
+    let diff_path = output_dir.join(format!("{}.diff.png", scene.name));
+    screenshot::save_png(&actual, &actual_path)?;
+    screenshot::save_png(&comparison.diff, &diff_path)?;
+    bail!(
+        "{:.3}% of the pixels differ from {:?}, at most {:.3}% may. See {:?} and {:?}",
+        comparison.differing_fraction() * 100.0,
+        reference_path,
+        tolerance.max_differing_fraction * 100.0,
+        actual_path,
+        diff_path,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]))
+    }
+
+    #[test]
+    fn color_difference_ranges_from_equal_to_black_on_white() {
+        let gray = Rgba([128, 128, 128, 255]);
+        assert_eq!(color_difference(gray, gray), 0.0);
+        let difference = color_difference(Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+        assert!(difference > 0.95 && difference <= 1.0, "{}", difference);
+        // Fully transparent pixels are white whatever their color
+        assert_eq!(color_difference(Rgba([0, 0, 0, 0]), Rgba([255, 255, 255, 255])), 0.0);
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = gradient(32, 32);
+        let comparison = compare(&image, &image, &Tolerance::default()).unwrap();
+        assert_eq!(comparison.differing_pixels, 0);
+        assert_eq!(comparison.total_pixels, 32 * 32);
+        assert!(comparison.matches(&Tolerance::default()));
+    }
+
+    #[test]
+    fn only_pixels_over_the_threshold_differ() {
+        let reference = RgbaImage::from_pixel(10, 10, Rgba([128, 128, 128, 255]));
+        let tolerance = Tolerance {
+            pixel_threshold: 0.1,
+            max_differing_fraction: 0.0,
+        };
+
+        let mut actual = reference.clone();
+        actual.put_pixel(3, 4, Rgba([255, 255, 255, 255]));
+        // A small change stays under the threshold
+        actual.put_pixel(5, 5, Rgba([130, 128, 128, 255]));
+        assert!(color_difference(*actual.get_pixel(3, 4), *reference.get_pixel(3, 4)) > tolerance.pixel_threshold);
+        assert!(color_difference(*actual.get_pixel(5, 5), *reference.get_pixel(5, 5)) <= tolerance.pixel_threshold);
+
+        let comparison = compare(&actual, &reference, &tolerance).unwrap();
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(*comparison.diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_ne!(*comparison.diff.get_pixel(5, 5), Rgba([255, 0, 0, 255]));
+        assert!(!comparison.matches(&tolerance));
+    }
+
+    #[test]
+    fn differing_fraction_is_compared_with_the_limit() {
+        let reference = RgbaImage::from_pixel(100, 10, Rgba([0, 0, 0, 255]));
+        let tolerance = Tolerance {
+            pixel_threshold: 0.1,
+            max_differing_fraction: 0.01,
+        };
+        let with_differing = |count: u32| {
+            let mut actual = reference.clone();
+            for x in 0..count {
+                actual.put_pixel(x, 0, Rgba([255, 255, 255, 255]));
+            }
+            compare(&actual, &reference, &tolerance).unwrap()
+        };
+
+        // 10 of 1000 pixels is right at the limit, one more is over it
+        assert!(with_differing(10).matches(&tolerance));
+        let over = with_differing(11);
+        assert_eq!(over.differing_pixels, 11);
+        assert!(!over.matches(&tolerance));
+    }
+
+    #[test]
+    fn images_of_different_sizes_do_not_compare() {
+        assert!(compare(&gradient(8, 8), &gradient(8, 4), &Tolerance::default()).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use iced_wgpu::wgpu;
+use image::RgbaImage;
+
+use crate::state::screenshot;
+use crate::state::state::State;
+
+// Rendering without a window, e.g. on CI machines:
+// --headless [--frames N] [--size WIDTHxHEIGHT] [--out DIR]
+pub struct HeadlessOptions {
+    pub frames: u32,
+    pub width: u32,
+    pub height: u32,
+    pub out_dir: PathBuf,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            frames: 1,
+            width: 800,
+            height: 600,
+            out_dir: PathBuf::from("frames"),
+        }
+    }
+}
+
+impl HeadlessOptions {
+    // None unless --headless is given
+    pub fn from_args() -> Result<Option<Self>> {
+        let mut options = Self::default();
+        let mut headless = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next()).with_context(|| format!("{} needs a value", name));
+
+            match name.as_str() {
+                "--headless" => headless = true,
+                "--frames" => options.frames = value()?.parse().context("--frames needs a number")?,
+                "--size" => {
+                    let size = value()?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                        .with_context(|| format!("--size needs WIDTHxHEIGHT, got {}", size))?;
+                    options.width = width;
+                    options.height = height;
+                }
+                "--out" => options.out_dir = PathBuf::from(value()?),
+                _ => {}
+            }
+        }
+
+        if options.width == 0 || options.height == 0 {
+            bail!("--size needs a width and height above 0");
+        }
+        Ok(headless.then_some(options))
+    }
+}
+
+// Waits for the startup assets, then renders and writes the frames as
+// out_dir/frame-0000.png and so on
+pub async fn run(options: HeadlessOptions) -> Result<()> {
+    let mut state = State::new_headless(options.width, options.height).await?;
+    // Same as the overlay's default background
+    state.color_pass.clear_color = wgpu::Color::BLACK;
+
+    state.finish_loading();
+    for failure in &state.loader.status().failures {
+        log::warn!("Asset failed to load: {}", failure);
+    }
+
+    for frame in 0..options.frames {
+        state.update();
+        let image = render_frame(&mut state).await?;
+        let path = options.out_dir.join(format!("frame-{:04}.png", frame));
+        screenshot::save_png(&image, &path)?;
+    }
+
+    log::info!("Wrote {} frames to {:?}", options.frames, options.out_dir);
+    Ok(())
+}
+
+// Draws the scene as it is and reads it back
+pub async fn render_frame(state: &mut State) -> Result<RgbaImage> {
+    let mut encoder = state.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Headless Encoder"),
+    });
+    state.render_scene(&mut encoder);
+    state.queue.submit(Some(encoder.finish()));
+
+    screenshot::read_target(&state.device, &state.queue, &state.scene_target).await
+}
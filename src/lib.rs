@@ -7,7 +7,7 @@ use winit::{
 use futures::task::SpawnExt;
 
 mod ui;
-use ui::controls::Controls;
+use ui::controls::{Controls, Message};
 use iced_wgpu::{wgpu, Backend, Renderer, Settings, Viewport};
 use iced_winit::{conversion, futures, program, winit, Clipboard, Debug, Size};
 mod util;
@@ -15,6 +15,10 @@ mod wasm;
 
 mod state;
 use state::state::State;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod golden;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
@@ -44,6 +48,23 @@ pub async fn start() {
     #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
 
+    // Renders a fixed number of frames to disk instead of opening a window
+    #[cfg(not(target_arch = "wasm32"))]
+    match headless::HeadlessOptions::from_args() {
+        Ok(None) => {}
+        Ok(Some(options)) => {
+            if let Err(e) = headless::run(options).await {
+                log::error!("Headless rendering failed: {:?}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            std::process::exit(2);
+        }
+    }
+
     let event_loop = EventLoop::new();
 
     #[cfg(target_arch = "wasm32")]
@@ -83,6 +104,7 @@ pub async fn start() {
     // Initialize staging belt and local pool
     let mut staging_belt = wgpu::util::StagingBelt::new(5 * 1024);
     let mut local_pool = futures::executor::LocalPool::new();
+    let mut load_status = Default::default();
 
 
     event_loop.run(move |event, _, control_flow| {
@@ -128,6 +150,13 @@ pub async fn start() {
                     state.input(event);
                 },
                 Event::MainEventsCleared => {
+                    // Forward asset loading progress to the overlay
+                    let new_load_status = state.loader.status();
+                    if new_load_status != load_status {
+                        iced_state.queue_message(Message::LoadStatusChanged(new_load_status.clone()));
+                        load_status = new_load_status;
+                    }
+
                     // If there are events pending
                     if !iced_state.is_queue_empty() {
                         // We update iced
@@ -146,7 +175,7 @@ pub async fn start() {
                     if resized {
                         let size = window.inner_size();
 
-                        state.surface.configure(
+                        state.surface().configure(
                             &state.device,
                             &wgpu::SurfaceConfiguration {
                                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -160,7 +189,7 @@ pub async fn start() {
                         resized = false;
                     }
 
-                    match state.surface.get_current_texture() {
+                    match state.surface().get_current_texture() {
                         Ok(frame) => {
                             let mut encoder = state.device.create_command_encoder(
                                 &wgpu::CommandEncoderDescriptor { label: None },
@@ -175,6 +204,8 @@ pub async fn start() {
                                     b: program.background_color().b as f64,
                                     a: program.background_color().a as f64,
                                 } ;
+                            state.color_pass.background = program.background();
+                            state.color_pass.set_shape(program.shape(), &state.assets);
 
                             // Draw the scene
                             state.update();
@@ -205,6 +236,8 @@ pub async fn start() {
                             staging_belt.finish();
                             state.queue.submit(Some(encoder.finish()));
                             frame.present();
+                            #[cfg(not(target_arch = "wasm32"))]
+                            state.save_requested_screenshot();
 
                             // Update the mouse cursor
                             window.set_cursor_icon(
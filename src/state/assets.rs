@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use super::render_components::model::{Material, Model, ModelVertexLayout};
+use super::render_components::texture::{load_texture_data, Texture, TextureData};
+use super::render_components::sampler::{SamplerCache, SamplerDesc};
+use super::render_components::pipeline_cache::PipelineCache;
+use crate::wasm::resources::{self, ModelData};
+
+// Typed id of an asset owned by the AssetManager. Handles are plain ids, the
+// asset stays alive until every acquired reference has been released.
+pub struct Handle<T> {
+    id: u64,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+// What an asset is cached under. Besides the file it was read from a key can
+// hold whatever else makes two loads of that file different assets.
+pub trait AssetKey: Clone + Eq + std::hash::Hash {
+    fn path(&self) -> &Path;
+}
+
+impl AssetKey for PathBuf {
+    fn path(&self) -> &Path {
+        self
+    }
+}
+
+// A texture owns its sampler, so the same image asked for with another
+// sampler is another texture
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub path: PathBuf,
+    pub sampler_desc: SamplerDesc,
+}
+
+impl AssetKey for TextureKey {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+struct Entry<T, K> {
+    asset: T,
+    key: Option<K>,
+    ref_count: usize,
+}
+
+pub struct AssetStore<T, K = PathBuf> {
+    entries: HashMap<u64, Entry<T, K>>,
+    by_key: HashMap<K, u64>,
+    next_id: u64,
+}
+
+impl<T, K: AssetKey> AssetStore<T, K> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_key: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    // Adds an asset with a reference count of one
+    pub fn insert(&mut self, asset: T, key: Option<K>) -> Handle<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(key) = &key {
+            self.by_key.insert(key.clone(), id);
+        }
+        self.entries.insert(id, Entry { asset, key, ref_count: 1 });
+        Handle { id, _marker: PhantomData }
+    }
+
+    // Returns the cached asset for key and takes another reference to it
+    pub fn acquire_key(&mut self, key: &K) -> Option<Handle<T>> {
+        let id = *self.by_key.get(key)?;
+        self.entries.get_mut(&id)?.ref_count += 1;
+        Some(Handle { id, _marker: PhantomData })
+    }
+
+    pub fn acquire(&mut self, handle: Handle<T>) -> Option<Handle<T>> {
+        self.entries.get_mut(&handle.id)?.ref_count += 1;
+        Some(handle)
+    }
+
+    // Drops one reference, the asset is removed and returned once none are left
+    pub fn release(&mut self, handle: Handle<T>) -> Option<T> {
+        let entry = self.entries.get_mut(&handle.id)?;
+        entry.ref_count -= 1;
+        if entry.ref_count > 0 {
+            return None;
+        }
+
+        let entry = self.entries.remove(&handle.id)?;
+        if let Some(key) = &entry.key {
+            self.by_key.remove(key);
+        }
+        Some(entry.asset)
+    }
+
+    // Every cached asset read from path, without taking references
+    pub fn handles_for_path(&self, path: &Path) -> Vec<Handle<T>> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.key.as_ref().is_some_and(|key| key.path() == path))
+            .map(|(&id, _)| Handle { id, _marker: PhantomData })
+            .collect()
+    }
+
+    // Swaps the asset behind handle, every holder of the handle sees the new one
+    pub fn replace(&mut self, handle: Handle<T>, asset: T) -> Option<T> {
+        let entry = self.entries.get_mut(&handle.id)?;
+        Some(std::mem::replace(&mut entry.asset, asset))
+    }
+
+    pub fn handles(&self) -> Vec<Handle<T>> {
+        self.entries.keys().map(|&id| Handle { id, _marker: PhantomData }).collect()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.entries
+            .iter_mut()
+            .map(|(&id, entry)| (Handle { id, _marker: PhantomData }, &mut entry.asset))
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.entries.get(&handle.id).map(|entry| &entry.asset)
+    }
+
+    pub fn key(&self, handle: Handle<T>) -> Option<&K> {
+        self.entries.get(&handle.id)?.key.as_ref()
+    }
+
+    pub fn path(&self, handle: Handle<T>) -> Option<&Path> {
+        Some(self.key(handle)?.path())
+    }
+
+    #[cfg(test)]
+    pub fn ref_count(&self, handle: Handle<T>) -> usize {
+        self.entries.get(&handle.id).map_or(0, |entry| entry.ref_count)
+    }
+}
+
+// Owns every loaded texture and model. Loading the same file twice hands out
+// the cached asset instead of reading and uploading it again.
+pub struct AssetManager {
+    pub textures: AssetStore<Texture, TextureKey>,
+    pub models: AssetStore<Model>,
+    pub samplers: SamplerCache,
+    pub pipelines: PipelineCache,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self {
+            textures: AssetStore::new(),
+            models: AssetStore::new(),
+            samplers: SamplerCache::new(),
+            pipelines: PipelineCache::new(),
+        }
+    }
+
+    pub fn add_texture(&mut self, texture: Texture) -> Handle<Texture> {
+        self.textures.insert(texture, None)
+    }
+
+    // Uploads already decoded texture data, unless a texture for path and
+    // sampler_desc is cached
+    pub fn add_texture_data(
+        &mut self,
+        path: &Path,
+        data: &TextureData,
+        sampler_desc: SamplerDesc,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let key = TextureKey { path: path.to_path_buf(), sampler_desc };
+        if let Some(handle) = self.textures.acquire_key(&key) {
+            return Ok(handle);
+        }
+
+        let texture = Texture::from_data(device, queue, data, path.to_str(), &mut self.samplers, &mut self.pipelines, sampler_desc)?;
+        Ok(self.textures.insert(texture, Some(key)))
+    }
+
+    // The model takes over the references held by its materials
+    pub fn add_model(&mut self, model: Model) -> Handle<Model> {
+        self.models.insert(model, None)
+    }
+
+    // Second half of a background load, see AssetLoader
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_model_data(
+        &mut self,
+        file_path: &Path,
+        file_name: &str,
+        data: ModelData,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        vertex_layout: ModelVertexLayout,
+    ) -> anyhow::Result<Handle<Model>> {
+        let path = file_path.join(file_name);
+        if let Some(handle) = self.models.acquire_key(&path) {
+            return Ok(handle);
+        }
+
+        let model = resources::upload_model(data, file_path, device, queue, layout, vertex_layout, self)?;
+        Ok(self.models.insert(model, Some(path)))
+    }
+
+    // Reads the file behind the textures loaded from path again and uploads it
+    // once per sampler they were asked for with. The textures are only
+    // swapped once every upload succeeded, then the bind groups of the
+    // materials using them are rebuilt. Returns false if no texture was loaded
+    // from path.
+    pub async fn reload_texture(
+        &mut self,
+        path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<bool> {
+        let handles = self.textures.handles_for_path(path);
+        if handles.is_empty() {
+            return Ok(false);
+        }
+        let (file_path, file_name) = split_path(path)?;
+        let data = load_texture_data(&resources::load_binary(file_path, file_name).await?)?;
+
+        let mut textures = Vec::with_capacity(handles.len());
+        for &handle in &handles {
+            let sampler_desc = self.textures.key(handle).unwrap().sampler_desc;
+            textures.push(Texture::from_data(device, queue, &data, path.to_str(), &mut self.samplers, &mut self.pipelines, sampler_desc)?);
+        }
+
+        for (&handle, texture) in handles.iter().zip(textures) {
+            self.textures.replace(handle, texture);
+
+            let texture = self.textures.get(handle).unwrap();
+            for (_, model) in self.models.iter_mut() {
+                for material in model.materials.iter_mut().filter(|m| m.diffuse_texture == handle) {
+                    let name = material.name.clone();
+                    *material = Material::new(device, &name, handle, texture, layout);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // Loads the model file behind handle again, the old model is only replaced
+    // if loading succeeds
+    pub async fn reload_model(
+        &mut self,
+        handle: Handle<Model>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        vertex_layout: ModelVertexLayout,
+    ) -> anyhow::Result<()> {
+        let path = self.models.path(handle)
+            .ok_or_else(|| anyhow::anyhow!("{:?} wasn't loaded from a file", handle))?
+            .to_path_buf();
+        let (file_path, file_name) = split_path(&path)?;
+
+        let model = resources::load_model(file_path, file_name, device, queue, layout, vertex_layout, self).await?;
+        if let Some(old) = self.models.replace(handle, model) {
+            for material in &old.materials {
+                self.release_texture(material.diffuse_texture);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn texture(&self, handle: Handle<Texture>) -> Option<&Texture> {
+        self.textures.get(handle)
+    }
+
+    pub fn model(&self, handle: Handle<Model>) -> Option<&Model> {
+        self.models.get(handle)
+    }
+
+    pub fn release_texture(&mut self, handle: Handle<Texture>) {
+        if self.textures.release(handle).is_some() {
+            log::info!("Unloaded {:?}", handle);
+        }
+    }
+
+    // Unloading a model also releases the textures of its materials
+    pub fn release_model(&mut self, handle: Handle<Model>) {
+        if let Some(model) = self.models.release(handle) {
+            for material in &model.materials {
+                self.release_texture(material.diffuse_texture);
+            }
+            log::info!("Unloaded {:?}", handle);
+        }
+    }
+}
+
+fn split_path(path: &Path) -> anyhow::Result<(&Path, &str)> {
+    let file_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("{:?} has no file name", path))?;
+    Ok((path.parent().unwrap_or_else(|| Path::new("")), file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn textures_are_cached_per_path_and_sampler() {
+        let mut store: AssetStore<&str, TextureKey> = AssetStore::new();
+        let repeat = SamplerDesc::default().with_address_mode(wgpu::AddressMode::Repeat);
+        let key = |path: &str, sampler_desc| TextureKey { path: PathBuf::from(path), sampler_desc };
+
+        let clamped = store.insert("clamped", Some(key("wood.png", SamplerDesc::default())));
+        assert_eq!(store.acquire_key(&key("wood.png", repeat)), None);
+        let repeated = store.insert("repeated", Some(key("wood.png", repeat)));
+
+        assert_ne!(clamped, repeated);
+        assert_eq!(store.acquire_key(&key("wood.png", repeat)), Some(repeated));
+        assert_eq!(store.ref_count(repeated), 2);
+        assert_eq!(store.ref_count(clamped), 1);
+
+        let mut reloaded = store.handles_for_path(Path::new("wood.png"));
+        reloaded.sort_by_key(|handle| handle.id);
+        assert_eq!(reloaded, [clamped, repeated]);
+
+        // Releasing one sampler's texture leaves the other one cached
+        assert_eq!(store.release(clamped), Some("clamped"));
+        assert_eq!(store.acquire_key(&key("wood.png", SamplerDesc::default())), None);
+        assert_eq!(store.handles_for_path(Path::new("wood.png")), [repeated]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, SystemTime};
+
+// Watch mode is enabled with HOT_RELOAD=1. Assets are then read from the source
+// tree instead of the copy build.rs puts into OUT_DIR, so edits show up
+// without a rebuild.
+pub fn enabled() -> bool {
+    matches!(std::env::var("HOT_RELOAD").as_deref(), Ok("1") | Ok("true"))
+}
+
+pub fn source_data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("data")
+}
+
+pub fn source_shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/state/shader/wgsl")
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Polls modification times of every file below the watched directories.
+// Polling keeps this free of platform specific notification APIs. The scan
+// runs on its own thread so large asset trees don't stall the frame loop.
+pub struct FileWatcher {
+    changes: Receiver<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        let (sender, changes) = mpsc::channel();
+        let mut modified = scan(&roots);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+
+            let files = scan(&roots);
+            for (path, time) in &files {
+                if modified.get(path) != Some(time) && sender.send(path.clone()).is_err() {
+                    // The watcher was dropped
+                    return;
+                }
+            }
+            modified = files;
+        });
+        Self { changes }
+    }
+
+    // Files that were added or modified since the last call
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = self.changes.try_iter().collect::<Vec<_>>();
+        // An editor can write a file more than once between two calls
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+}
+
+fn scan(roots: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut files = HashMap::new();
+    let mut pending = roots.to_vec();
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Can't watch {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => pending.push(path),
+                Ok(metadata) => {
+                    if let Ok(modified) = metadata.modified() {
+                        files.insert(path, modified);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    files
+}
@@ -0,0 +1,304 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::state::render_components::sampler::SamplerDesc;
+use crate::state::render_components::texture::{self, TextureData};
+use crate::wasm::resources::{self, ModelData};
+use crate::wasm::vfs;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadStatus {
+    pub pending_jobs: usize,
+    pub files_requested: usize,
+    pub files_loaded: usize,
+    pub bytes_loaded: usize,
+    pub failures: Vec<String>,
+}
+
+impl LoadStatus {
+    pub fn is_loading(&self) -> bool {
+        self.pending_jobs > 0
+    }
+}
+
+// Progress shared between the loader and its running jobs
+#[derive(Clone, Default)]
+pub struct LoadProgress(Arc<Mutex<LoadStatus>>);
+
+impl LoadProgress {
+    // vfs::read that counts the file and its bytes
+    pub async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        self.0.lock().unwrap().files_requested += 1;
+        let data = vfs::read(path).await?;
+
+        let mut status = self.0.lock().unwrap();
+        status.files_loaded += 1;
+        status.bytes_loaded += data.len();
+        Ok(data)
+    }
+
+    pub fn status(&self) -> LoadStatus {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn start_job(&self, name: String) -> Job {
+        self.0.lock().unwrap().pending_jobs += 1;
+        Job {
+            progress: self.clone(),
+            name,
+            finished: false,
+        }
+    }
+
+    fn job_finished(&self, error: Option<String>) {
+        let mut status = self.0.lock().unwrap();
+        status.pending_jobs -= 1;
+        status.failures.extend(error);
+    }
+}
+
+// A running job. If it's dropped without finish, e.g. because the job
+// panicked, it still counts as finished and is reported as a failure, so
+// waiting for the loader doesn't hang.
+struct Job {
+    progress: LoadProgress,
+    name: String,
+    finished: bool,
+}
+
+impl Job {
+    fn finish(mut self, error: Option<String>) {
+        self.finished = true;
+        self.progress.job_finished(error);
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        if !self.finished {
+            log::error!("Loading {} panicked", self.name);
+            self.progress.job_finished(Some(format!("{}: loading panicked", self.name)));
+        }
+    }
+}
+
+// CPU side results, they still have to be uploaded on the render thread
+pub enum LoadedAsset {
+    Model {
+        file_path: PathBuf,
+        file_name: String,
+        data: ModelData,
+    },
+    Cubemap {
+        name: String,
+        faces: Vec<image::DynamicImage>,
+    },
+    Texture {
+        path: PathBuf,
+        sampler_desc: SamplerDesc,
+        data: TextureData,
+    },
+}
+
+// Reads and decodes assets in the background, on a thread pool natively and as
+// spawned futures on the web. Results are picked up with poll.
+pub struct AssetLoader {
+    progress: LoadProgress,
+    sender: mpsc::Sender<LoadedAsset>,
+    receiver: mpsc::Receiver<LoadedAsset>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pool: ThreadPool,
+}
+
+impl AssetLoader {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            progress: LoadProgress::default(),
+            sender,
+            receiver,
+            #[cfg(not(target_arch = "wasm32"))]
+            pool: ThreadPool::new(
+                std::thread::available_parallelism().map_or(2, |n| n.get()).min(4),
+            ),
+        }
+    }
+
+    pub fn load_model(&self, file_path: &Path, file_name: &str) {
+        let file_path = file_path.to_path_buf();
+        let file_name = file_name.to_string();
+        let progress = self.progress.clone();
+        let sender = self.sender.clone();
+
+        let job = self.progress.start_job(file_path.join(&file_name).display().to_string());
+        self.spawn(move || async move {
+            let error = match resources::read_model(&file_path, &file_name, &progress).await {
+                Ok(data) => {
+                    let _ = sender.send(LoadedAsset::Model { file_path, file_name, data });
+                    None
+                }
+                Err(e) => {
+                    log::error!("Failed to load {:?}: {:?}", file_path.join(&file_name), e);
+                    Some(format!("{}: {}", file_path.join(&file_name).display(), e))
+                }
+            };
+            job.finish(error);
+        });
+    }
+
+    pub fn load_cubemap(&self, files: Vec<PathBuf>) {
+        let name = files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(", ");
+        let progress = self.progress.clone();
+        let sender = self.sender.clone();
+
+        let job = self.progress.start_job(name.clone());
+        self.spawn(move || async move {
+            let error = match resources::read_cubemap(&files, &progress).await {
+                Ok(faces) => {
+                    let _ = sender.send(LoadedAsset::Cubemap { name, faces });
+                    None
+                }
+                Err(e) => {
+                    log::error!("Failed to load {}: {:?}", name, e);
+                    Some(format!("{}: {}", name, e))
+                }
+            };
+            job.finish(error);
+        });
+    }
+
+    pub fn load_texture(&self, path: &Path, sampler_desc: SamplerDesc) {
+        let path = path.to_path_buf();
+        let progress = self.progress.clone();
+        let sender = self.sender.clone();
+
+        let job = self.progress.start_job(path.display().to_string());
+        self.spawn(move || async move {
+            let data = match progress.read(&path).await {
+                Ok(bytes) => texture::load_texture_data(&bytes),
+                Err(e) => Err(e),
+            };
+            let error = match data {
+                Ok(data) => {
+                    let _ = sender.send(LoadedAsset::Texture { path, sampler_desc, data });
+                    None
+                }
+                Err(e) => {
+                    log::error!("Failed to load {:?}: {:?}", path, e);
+                    Some(format!("{}: {}", path.display(), e))
+                }
+            };
+            job.finish(error);
+        });
+    }
+
+    // The future is created on the thread that runs it, so it doesn't need to be Send
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn<F, Fut>(&self, job: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.pool.execute(move || pollster::block_on(job()));
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn<F, Fut>(&self, job: F)
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        wasm_bindgen_futures::spawn_local(job());
+    }
+
+    // Assets that finished loading since the last call
+    pub fn poll(&self) -> Vec<LoadedAsset> {
+        self.receiver.try_iter().collect()
+    }
+
+    // For errors that happen after the background part, e.g. while uploading
+    pub fn report_failure(&self, message: String) {
+        self.progress.0.lock().unwrap().failures.push(message);
+    }
+
+    pub fn status(&self) -> LoadStatus {
+        self.progress.status()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type PoolJob = Box<dyn FnOnce() + Send>;
+
+#[cfg(not(target_arch = "wasm32"))]
+struct ThreadPool {
+    jobs: mpsc::Sender<PoolJob>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ThreadPool {
+    // The workers exit once the pool and with it the sender is dropped
+    fn new(threads: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<PoolJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("asset loader {}", i))
+                .spawn(move || loop {
+                    // Only hold the lock while waiting, not while running the job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // A panicking job reports itself, the worker stays around
+                        Ok(job) => {
+                            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+                        }
+                        Err(_) => break,
+                    }
+                })
+                .expect("Failed to spawn asset loader thread");
+        }
+
+        Self { jobs }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.jobs.send(Box::new(job)).expect("Asset loader threads are gone");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_jobs_report_their_error() {
+        let progress = LoadProgress::default();
+        progress.start_job("a".to_string()).finish(None);
+        progress.start_job("b".to_string()).finish(Some("b: broken".to_string()));
+
+        let status = progress.status();
+        assert!(!status.is_loading());
+        assert_eq!(status.failures, ["b: broken"]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn panicking_jobs_count_as_failed() {
+        let loader = AssetLoader::new();
+        // More panics than workers, so the workers have to survive them
+        for _ in 0..8 {
+            let job = loader.progress.start_job("panics".to_string());
+            loader.spawn(move || async move {
+                let _job = job;
+                panic!("job panicked");
+            });
+        }
+
+        while loader.status().is_loading() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(loader.status().failures, vec!["panics: loading panicked"; 8]);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod state;
 pub mod render_components;
+pub mod assets;
+pub mod loader;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
+pub mod screenshot;
@@ -32,6 +32,45 @@ impl Camera {
         let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
         return OPENGL_TO_WGPU_MATRIX * proj * view * cgmath::Matrix4::from_angle_z(z_object_rotation);
     }
+
+    // Moves the eye along the current view direction so that a sphere of the
+    // given radius fills the view, and fits the clip planes around it.
+    pub fn frame_sphere(&mut self, center: cgmath::Point3<f32>, radius: f32) {
+        use cgmath::InnerSpace;
+        let radius = radius.max(0.001);
+
+        let mut direction = self.eye - self.target;
+        if direction.magnitude2() <= f32::EPSILON {
+            direction = cgmath::Vector3::unit_z();
+        }
+        let direction = direction.normalize();
+
+        // Use whichever field of view is narrower so the sphere fits both ways
+        let half_fovy = cgmath::Rad::from(cgmath::Deg(self.fovy)).0 * 0.5;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        let half_fov = half_fovy.min(half_fovx);
+        let distance = radius / half_fov.sin();
+
+        self.target = center;
+        self.eye = center + direction * distance;
+        // Leave some room so the camera controller can still move around,
+        // towards the sphere as well as away from it
+        self.znear = ((distance - radius) * 0.1).max(distance * 0.001);
+        self.zfar = (distance + radius) * 4.0;
+    }
+
+    // Ray from the eye through a point in normalized device coordinates,
+    // x and y in -1..1 with +y up. Returns the origin and a unit direction.
+    pub fn ray(&self, x: f32, y: f32) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        use cgmath::InnerSpace;
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let tan_half_fovy = (cgmath::Rad::from(cgmath::Deg(self.fovy)).0 * 0.5).tan();
+
+        let direction = forward + right * (x * tan_half_fovy * self.aspect) + up * (y * tan_half_fovy);
+        (self.eye, direction.normalize())
+    }
 }
 
 // We need this for Rust to store our data correctly for the shaders
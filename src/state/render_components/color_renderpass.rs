@@ -1,3 +1,6 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use wgpu::util::DeviceExt;
 use cgmath::*;
 
@@ -5,40 +8,101 @@ use winit::{
     event::*,
 };
 
-use super::render_pipeline;
+use super::render_pipeline::{PolygonView, RenderPipelineBuilder, WireframeMethod};
+use super::pipeline_cache::PipelineCache;
+use super::primitives::{self, Shape};
 
 use super::texture::Texture;
-use super::model::{Vertex, ModelVertex, Model, DrawModel, DrawLight};
+use super::render_target::{RenderTarget, RenderTargetDesc};
+use super::sampler::SamplerDesc;
+use super::model::{Material, MaterialArray, Model, ModelVertexLayout, BoundingBox, DrawModel, DrawLight};
 use super::camera::CameraResources;
 use super::instance::{Instance, InstanceRaw};
 use super::light::LightResources;
+use super::skybox::{self, Background, Skybox};
+use super::ibl::Ibl;
 use super::instance::NUM_INSTANCES_PER_ROW;
 
 use crate::util::math_funcs::quat_mul;
 use crate::util::toggle_bool::BoolToggleExt;
-use crate::wasm::resources;
+use crate::state::assets::{AssetManager, Handle};
+use crate::state::loader::AssetLoader;
 
 const ROTATION_SPEED: f32 = 2.0 * std::f32::consts::PI / 180.0;
+// Shader modules are labeled with their file, hot reload finds them by it
+const COLOR_SHADER_FILE: &str = "shader.wgsl";
+const LIGHT_SHADER_FILE: &str = "light.wgsl";
+const WIREFRAME_SHADER_FILE: &str = "wireframe.wgsl";
+// Extra layers for the texture array demo
+const ARRAY_TEXTURE_FILES: [&str; 2] = ["happy-tree.png", "happy-tree-cartoon.png"];
+const DEBUG_VIEWS: [PolygonView; 3] = [PolygonView::Wireframe, PolygonView::WireframeOverShaded, PolygonView::Points];
 
 pub struct ColorPass {
     pub clear_color: wgpu::Color,
+    pub background: Background,
+    skybox: Skybox,
+    ibl: Ibl,
     instance_buffer: wgpu::Buffer,
     instances: Vec<Instance>,
-    render_pipeline: wgpu::RenderPipeline,
-    light_render_pipeline: wgpu::RenderPipeline,
+    render_pipeline: Arc<wgpu::RenderPipeline>,
+    array_render_pipeline: Arc<wgpu::RenderPipeline>,
+    light_render_pipeline: Arc<wgpu::RenderPipeline>,
+    // One pipeline for each of DEBUG_VIEWS
+    debug_pipelines: Vec<(PolygonView, Arc<wgpu::RenderPipeline>)>,
+    pub polygon_view: PolygonView,
+    wireframe_method: WireframeMethod,
+    render_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    array_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    light_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    // Format and sample count of the target the pipelines draw into
+    target: RenderTargetDesc,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_array_bind_group_layout: wgpu::BindGroupLayout,
+    pub vertex_layout: ModelVertexLayout,
     pub camera_resources: CameraResources,
     light_resources: LightResources,
     space_state_on: bool,
     size: winit::dpi::PhysicalSize<u32>,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    // Instance picked with a left click, G frames it
+    selected_instance: Option<usize>,
     object_rotation: Deg<f32>,
-    model: Model,
+    // None until the background load of model_path finished, the placeholder
+    // is drawn in its place meanwhile
+    model: Option<Handle<Model>>,
+    model_path: PathBuf,
+    placeholder_model: Handle<Model>,
+    // Generated models picked in the overlay replace the loaded one
+    pub shape: Option<Shape>,
+    shape_models: Vec<(Shape, Handle<Model>)>,
+    light_model: Handle<Model>,
+    // Textures packed into material_array next to the model's own ones, so
+    // the instance grid has something to tell apart. T switches between the
+    // model's materials and one draw with the array.
+    array_textures: Vec<Handle<Texture>>,
+    material_array: Option<MaterialArray>,
+    use_texture_array: bool,
+    // Instances are sorted by LOD each frame, lod_instance_ranges[lod] is the
+    // part of the instance buffer that is drawn with that LOD
+    lod_instance_ranges: Vec<std::ops::Range<u32>>,
 }
 
 impl ColorPass {
-    pub async fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, queue: &wgpu::Queue) -> Self {    
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        queue: &wgpu::Queue,
+        target: &RenderTargetDesc,
+        assets: &mut AssetManager,
+        loader: &AssetLoader,
+    ) -> Self {    
         let clear_color = wgpu::Color::GREEN;
+        // The quantized layout trades precision for vertex bandwidth
+        let vertex_layout = ModelVertexLayout::from_env().unwrap_or(ModelVertexLayout::Full);
         let camera_resources = CameraResources::new(&config, &device).unwrap();
         let light_resources = LightResources::new(&device, [5.0, 5.0, 0.0], [1.0, 1.0, 1.0]);
+        let skybox = Skybox::new(device, queue, target, &camera_resources.camera, &mut assets.samplers, &mut assets.pipelines).unwrap();
+        let ibl = Ibl::new(device, queue, skybox.texture(), &mut assets.samplers, &mut assets.pipelines);
 
         let texture_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
@@ -64,57 +128,102 @@ impl ColorPass {
             }
         );
 
-        let render_pipeline = {
-            let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_resources.camera_bind_group_layout,
-                    &light_resources.light_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Color Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shader/wgsl/shader.wgsl").into()),
-            };
-
-            render_pipeline::create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let render_pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_resources.camera_bind_group_layout,
+                &light_resources.light_bind_group_layout,
+                &ibl.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        }));
+        let render_pipeline = Self::create_color_pipeline(
+            device,
+            &mut assets.pipelines,
+            &render_pipeline_layout,
+            target,
+            vertex_layout,
+            include_str!("../shader/wgsl/shader.wgsl"),
+            "fs_main",
+        );
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[
-                    &camera_resources.camera_bind_group_layout, 
-                    &light_resources.light_bind_group_layout
+        // Same shader, but materials come from the layers of one array texture
+        let texture_array_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
                 ],
-                push_constant_ranges: &[],
-            });
+                label: Some("texture_array_bind_group_layout"),
+            }
+        );
+        let array_pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Texture Array Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_array_bind_group_layout,
+                &camera_resources.camera_bind_group_layout,
+                &light_resources.light_bind_group_layout,
+                &ibl.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        }));
+        let array_render_pipeline = Self::create_color_pipeline(
+            device,
+            &mut assets.pipelines,
+            &array_pipeline_layout,
+            target,
+            vertex_layout,
+            include_str!("../shader/wgsl/shader.wgsl"),
+            "fs_array",
+        );
 
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shader/wgsl/light.wgsl").into()),
-            };
+        let debug_pipelines = DEBUG_VIEWS
+            .iter()
+            .map(|&view| {
+                let pipeline = Self::create_debug_pipeline(
+                    device,
+                    &mut assets.pipelines,
+                    &render_pipeline_layout,
+                    target,
+                    vertex_layout,
+                    include_str!("../shader/wgsl/wireframe.wgsl"),
+                    view,
+                );
+                (view, pipeline)
+            })
+            .collect();
 
-            render_pipeline::create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc()],
-                shader,
-            )
-        };
+        let light_pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[
+                &camera_resources.camera_bind_group_layout, 
+                &light_resources.light_bind_group_layout
+            ],
+            push_constant_ranges: &[],
+        }));
+        let light_render_pipeline = Self::create_light_pipeline(
+            device,
+            &mut assets.pipelines,
+            &light_pipeline_layout,
+            target,
+            vertex_layout,
+            include_str!("../shader/wgsl/light.wgsl"),
+        );
 
         
         const SPACE_BETWEEN: f32 = 3.0;
@@ -135,7 +244,7 @@ impl ColorPass {
                 };
 
                 Instance {
-                    position, rotation,
+                    position, rotation, texture_layer: 0,
                 }
             })
         }).collect::<Vec<_>>();
@@ -152,41 +261,403 @@ impl ColorPass {
         let size = winit::dpi::PhysicalSize::new(config.width, config.height);
         let object_rotation = cgmath::Deg(0.0f32);
 
-        let model = resources::load_model(
-            std::path::Path::new("models/cube/"),
-            "cube.obj",
-            &device,
-            &queue,
+        let (model_dir, model_file) = (Path::new("models/cube/"), "cube.obj");
+        loader.load_model(model_dir, model_file);
+        if let Some(files) = skybox::files_from_env() {
+            loader.load_cubemap(files);
+        }
+
+        let placeholder_texture = Texture::from_color(device, queue, [128, 128, 128, 255], "Placeholder", &mut assets.samplers, &mut assets.pipelines).unwrap();
+        let placeholder_texture = assets.add_texture(placeholder_texture);
+        let placeholder_material = Material::new(
+            device,
+            "Placeholder",
+            placeholder_texture,
+            assets.texture(placeholder_texture).unwrap(),
             &texture_bind_group_layout,
-        ).await.unwrap();
+        );
+        let placeholder_model = assets.add_model(
+            primitives::uv_sphere(0.5, 16, 8)
+                .into_model(device, "Placeholder", placeholder_material, vertex_layout),
+        );
+
+        let shape_models = Shape::ALL
+            .iter()
+            .map(|&shape| {
+                let texture = assets.textures.acquire(placeholder_texture).unwrap();
+                let material = Material::new(
+                    device,
+                    shape.name(),
+                    texture,
+                    assets.texture(texture).unwrap(),
+                    &texture_bind_group_layout,
+                );
+                let model = shape.generate().into_lod_model(device, shape.name(), material, vertex_layout);
+                (shape, assets.add_model(model))
+            })
+            .collect();
+
+        // The light shader only reads positions, the material just completes the model
+        let light_texture = Texture::from_color(device, queue, [255, 255, 255, 255], "Light Gizmo", &mut assets.samplers, &mut assets.pipelines).unwrap();
+        let light_texture = assets.add_texture(light_texture);
+        let light_material = Material::new(
+            device,
+            "Light Gizmo",
+            light_texture,
+            assets.texture(light_texture).unwrap(),
+            &texture_bind_group_layout,
+        );
+        let light_model = assets.add_model(
+            primitives::icosphere(1.0, 2)
+                .into_model(device, "Light Gizmo", light_material, vertex_layout),
+        );
+
+        for file_name in ARRAY_TEXTURE_FILES {
+            loader.load_texture(Path::new(file_name), SamplerDesc::default());
+        }
 
-        Self {
+        let mut color_pass = Self {
             clear_color, 
+            background: Background::default(),
+            skybox,
+            ibl,
             instance_buffer,
             instances,
             render_pipeline,
+            array_render_pipeline,
             light_render_pipeline,
+            debug_pipelines,
+            polygon_view: PolygonView::default(),
+            wireframe_method: WireframeMethod::for_features(device.features()),
+            render_pipeline_layout,
+            array_pipeline_layout,
+            light_pipeline_layout,
+            target: target.clone(),
+            texture_bind_group_layout,
+            texture_array_bind_group_layout,
+            vertex_layout,
             camera_resources,
             light_resources,
             space_state_on: false,
             size,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            selected_instance: None,
             object_rotation,
-            model,
+            model: None,
+            model_path: model_dir.join(model_file),
+            placeholder_model,
+            shape: None,
+            shape_models,
+            light_model,
+            array_textures: Vec::new(),
+            material_array: None,
+            use_texture_array: false,
+            lod_instance_ranges: Vec::new(),
+        };
+        color_pass.frame_all(assets);
+
+        color_pass
+    }
+
+    // Switches from the placeholder to a model once its background load finished.
+    // Returns false if the model isn't the one this pass is waiting for.
+    pub fn model_loaded(
+        &mut self,
+        path: &Path,
+        handle: Handle<Model>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &AssetManager,
+    ) -> bool {
+        if self.model.is_some() || path != self.model_path {
+            return false;
+        }
+        self.model = Some(handle);
+        self.frame_all(assets);
+        self.build_material_array(device, queue, assets);
+        true
+    }
+
+    // Adds one of ARRAY_TEXTURE_FILES to the texture array once its background
+    // load finished. Returns false if the pass doesn't use the texture.
+    pub fn texture_loaded(
+        &mut self,
+        path: &Path,
+        handle: Handle<Texture>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &AssetManager,
+    ) -> bool {
+        if !ARRAY_TEXTURE_FILES.iter().any(|file_name| path == Path::new(file_name)) || self.array_textures.contains(&handle) {
+            return false;
+        }
+        self.array_textures.push(handle);
+        self.build_material_array(device, queue, assets);
+        true
+    }
+
+    // Packs the texture array again if one of its layers was copied from
+    // path, so a hot reloaded texture shows up there too
+    pub fn texture_reloaded(&mut self, path: &Path, device: &wgpu::Device, queue: &wgpu::Queue, assets: &AssetManager) {
+        let packed = self.material_array.as_ref().is_some_and(|material_array| {
+            material_array.diffuse_textures.iter().any(|&handle| assets.textures.path(handle) == Some(path))
+        });
+        if packed {
+            self.build_material_array(device, queue, assets);
+        }
+    }
+
+    // Packs the model's textures and array_textures that have the same size
+    // as the first one, and spreads the layers over the instances
+    fn build_material_array(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, assets: &AssetManager) {
+        let model = match self.model.and_then(|handle| assets.model(handle)) {
+            Some(model) => model,
+            None => return,
+        };
+        let mut candidates = model.materials.iter().map(|material| material.diffuse_texture).collect::<Vec<_>>();
+        candidates.extend(self.array_textures.iter().copied());
+
+        let mut textures: Vec<(Handle<Texture>, &Texture)> = Vec::new();
+        for handle in candidates {
+            if textures.iter().any(|(packed, _)| *packed == handle) {
+                continue;
+            }
+            let texture = match assets.texture(handle) {
+                Some(texture) => texture,
+                None => continue,
+            };
+            let fits = textures.first().is_none_or(|(_, first)| {
+                texture.size == first.size && texture.format == first.format && texture.mip_level_count == first.mip_level_count
+            });
+            if fits {
+                textures.push((handle, texture));
+            }
+        }
+        if textures.len() < 2 {
+            log::info!("Not enough textures of the same size for a texture array");
+            return;
+        }
+
+        match MaterialArray::new(device, queue, "Material Array", &textures, &self.texture_array_bind_group_layout) {
+            Ok(material_array) => {
+                let layer_count = material_array.layer_count();
+                for (i, instance) in self.instances.iter_mut().enumerate() {
+                    instance.texture_layer = i as u32 % layer_count;
+                }
+                self.material_array = Some(material_array);
+            }
+            Err(e) => log::error!("Failed to build the texture array: {:?}", e),
+        }
+    }
+
+    // Shows texture as the skybox and lights the scene with it
+    pub fn set_environment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, texture: Texture) {
+        self.skybox.set_texture(device, texture);
+        self.ibl.update(device, queue, self.skybox.texture());
+    }
+
+    fn scene_model(&self) -> Handle<Model> {
+        let shape_model = self.shape.and_then(|shape| {
+            self.shape_models.iter().find(|(other, _)| *other == shape).map(|(_, model)| *model)
+        });
+        shape_model.or(self.model).unwrap_or(self.placeholder_model)
+    }
+
+    // Shows a generated shape instead of the loaded model, or the model again
+    // with None
+    pub fn set_shape(&mut self, shape: Option<Shape>, assets: &AssetManager) {
+        if shape != self.shape {
+            self.shape = shape;
+            self.frame_all(assets);
+        }
+    }
+
+    fn create_color_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &Arc<wgpu::PipelineLayout>,
+        target: &RenderTargetDesc,
+        vertex_layout: ModelVertexLayout,
+        source: &str,
+        fragment_entry_point: &str,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some(COLOR_SHADER_FILE),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        };
+
+        let vertex_layouts = [vertex_layout.desc(), InstanceRaw::desc()];
+        let builder = RenderPipelineBuilder::new()
+            .layout(layout)
+            .shader(shader)
+            .fragment_entry_point(fragment_entry_point)
+            .vertex_layouts(&vertex_layouts)
+            .render_target(target);
+        pipelines.get(device, builder)
+    }
+
+    // Shares the layout of the shaded pipeline, so the same draw calls work
+    fn create_debug_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &Arc<wgpu::PipelineLayout>,
+        target: &RenderTargetDesc,
+        vertex_layout: ModelVertexLayout,
+        source: &str,
+        view: PolygonView,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some(WIREFRAME_SHADER_FILE),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        };
+
+        let vertex_layouts = [vertex_layout.desc(), InstanceRaw::desc()];
+        let builder = RenderPipelineBuilder::new()
+            .label("Debug View Pipeline")
+            .layout(layout)
+            .shader(shader)
+            .vertex_layouts(&vertex_layouts)
+            .render_target(target)
+            .polygon_view(view, device.features());
+        pipelines.get(device, builder)
+    }
+
+    fn create_light_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &Arc<wgpu::PipelineLayout>,
+        target: &RenderTargetDesc,
+        vertex_layout: ModelVertexLayout,
+        source: &str,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some(LIGHT_SHADER_FILE),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        };
+
+        let vertex_layouts = [vertex_layout.desc()];
+        let builder = RenderPipelineBuilder::new()
+            .label("Light Pipeline")
+            .layout(layout)
+            .shader(shader)
+            .vertex_layouts(&vertex_layouts)
+            .render_target(target);
+        pipelines.get(device, builder)
+    }
+
+    // Rebuilds the pipelines using shader, a path relative to the shader
+    // directory, from new source. Errors are captured instead of going to the
+    // uncaptured error handler, and the current pipelines stay in use if there
+    // are any. Returns false if shader isn't a shader of this pass.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shader: &std::path::Path,
+        source: &str,
+    ) -> anyhow::Result<bool> {
+        // A file with the same name in a subdirectory is something else
+        let file_name = match shader.to_str() {
+            Some(file_name) => file_name,
+            None => return Ok(false),
+        };
+        match file_name {
+            COLOR_SHADER_FILE | LIGHT_SHADER_FILE | WIREFRAME_SHADER_FILE => {}
+            skybox::SHADER_FILE => {
+                self.skybox.reload_shader(device, pipelines, source)?;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        }
+
+        // Nothing will ask for pipelines with the old source again
+        pipelines.invalidate_shader(file_name);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let reloaded = match file_name {
+            COLOR_SHADER_FILE => vec![
+                Self::create_color_pipeline(
+                    device,
+                    pipelines,
+                    &self.render_pipeline_layout,
+                    &self.target,
+                    self.vertex_layout,
+                    source,
+                    "fs_main",
+                ),
+                Self::create_color_pipeline(
+                    device,
+                    pipelines,
+                    &self.array_pipeline_layout,
+                    &self.target,
+                    self.vertex_layout,
+                    source,
+                    "fs_array",
+                ),
+            ],
+            LIGHT_SHADER_FILE => vec![Self::create_light_pipeline(
+                device,
+                pipelines,
+                &self.light_pipeline_layout,
+                &self.target,
+                self.vertex_layout,
+                source,
+            )],
+            _ => DEBUG_VIEWS
+                .iter()
+                .map(|&view| {
+                    Self::create_debug_pipeline(
+                        device,
+                        pipelines,
+                        &self.render_pipeline_layout,
+                        &self.target,
+                        self.vertex_layout,
+                        source,
+                        view,
+                    )
+                })
+                .collect(),
+        };
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            // Don't hand out the broken pipelines later on
+            pipelines.invalidate_shader(file_name);
+            anyhow::bail!("{}", error);
+        }
+
+        let mut reloaded = reloaded.into_iter();
+        match file_name {
+            COLOR_SHADER_FILE => {
+                self.render_pipeline = reloaded.next().unwrap();
+                self.array_render_pipeline = reloaded.next().unwrap();
+            }
+            LIGHT_SHADER_FILE => self.light_render_pipeline = reloaded.next().unwrap(),
+            _ => self.debug_pipelines = DEBUG_VIEWS.iter().copied().zip(reloaded).collect(),
         }
+        Ok(true)
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+    pub fn resize(&mut self, config: &wgpu::SurfaceConfiguration) {
         self.camera_resources.camera.aspect = config.width as f32 / config.height as f32;
         self.size = winit::dpi::PhysicalSize::new(config.width, config.height);
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
+    pub fn input(&mut self, event: &WindowEvent, assets: &AssetManager) -> bool {
         self.camera_resources.camera_controller.process_events(event);
 
         match event {
             WindowEvent::CursorMoved { 
                 position, ..
             } => {
+                self.cursor_position = *position;
+                false
+            },
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.selected_instance = self.pick_instance(assets);
+                log::info!("Selected instance: {:?}", self.selected_instance);
                 false
             },
             WindowEvent::KeyboardInput {
@@ -201,11 +672,148 @@ impl ColorPass {
                 self.space_state_on.toggle();
                 true
             },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F),
+                        ..
+                    },
+                ..
+            } => {
+                self.frame_all(assets);
+                true
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        ..
+                    },
+                ..
+            } => {
+                self.frame_selection(assets);
+                true
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::T),
+                        ..
+                    },
+                ..
+            } => {
+                self.use_texture_array.toggle();
+                true
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                ..
+            } => {
+                self.polygon_view = self.polygon_view.next();
+                log::info!("Polygon view: {:?}", self.polygon_view);
+                true
+            },
             _ => false,
         }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    // Bounds of the whole instance grid. Instances keep rotating, so each one is
+    // padded by the sphere that encloses the model around its own origin.
+    pub fn scene_bounds(&self, assets: &AssetManager) -> Option<BoundingBox> {
+        let reach = self.instance_reach(assets)?;
+        self.instances
+            .iter()
+            .map(|instance| Self::instance_bounds(instance, reach))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    // Radius of the sphere around an instance's origin that holds the model in
+    // any rotation
+    fn instance_reach(&self, assets: &AssetManager) -> Option<f32> {
+        let model_bounds = assets.model(self.scene_model())?.bounds()?;
+        Some(model_bounds.center().to_vec().magnitude() + model_bounds.radius())
+    }
+
+    fn instance_bounds(instance: &Instance, reach: f32) -> BoundingBox {
+        BoundingBox {
+            min: Point3::from_vec(instance.position - Vector3::new(reach, reach, reach)),
+            max: Point3::from_vec(instance.position + Vector3::new(reach, reach, reach)),
+        }
+    }
+
+    pub fn frame_all(&mut self, assets: &AssetManager) {
+        if let Some(bounds) = self.scene_bounds(assets) {
+            self.camera_resources.camera.frame_sphere(bounds.center(), bounds.radius());
+        }
+    }
+
+    // Frames the selected instance, or the whole scene without a selection
+    pub fn frame_selection(&mut self, assets: &AssetManager) {
+        let selected = self.selected_instance.and_then(|index| self.instances.get(index));
+        match (selected, self.instance_reach(assets)) {
+            (Some(instance), Some(reach)) => {
+                let bounds = Self::instance_bounds(instance, reach);
+                self.camera_resources.camera.frame_sphere(bounds.center(), bounds.radius());
+            }
+            _ => self.frame_all(assets),
+        }
+    }
+
+    // Nearest instance whose bounding sphere is under the cursor
+    fn pick_instance(&self, assets: &AssetManager) -> Option<usize> {
+        let reach = self.instance_reach(assets)?;
+        let x = (2.0 * self.cursor_position.x / self.size.width.max(1) as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * self.cursor_position.y / self.size.height.max(1) as f64) as f32;
+        let (origin, direction) = self.camera_resources.camera.ray(x, y);
+
+        self.instances
+            .iter()
+            .enumerate()
+            .filter_map(|(index, instance)| {
+                // Ray and sphere, direction has unit length
+                let to_center = Point3::from_vec(instance.position) - origin;
+                let along = to_center.dot(direction);
+                let distance2 = to_center.magnitude2() - along * along;
+                (along > 0.0 && distance2 <= reach * reach).then_some((index, along))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    // Picks a LOD for every instance from its projected size and groups the
+    // raw instance data so every LOD is one contiguous range
+    fn sort_instances_by_lod(&mut self, model: &Model) -> Vec<InstanceRaw> {
+        let camera = &self.camera_resources.camera;
+        let reach = model.bounds()
+            .map_or(0.0, |bounds| bounds.center().to_vec().magnitude() + bounds.radius());
+        let tan_half_fovy = (cgmath::Rad::from(cgmath::Deg(camera.fovy)).0 * 0.5).tan();
+
+        let mut buckets = vec![Vec::new(); model.lod_count()];
+        for instance in &self.instances {
+            let distance = (Point3::from_vec(instance.position) - camera.eye).magnitude();
+            let screen_size = reach / (distance.max(f32::EPSILON) * tan_half_fovy);
+            buckets[model.select_lod(screen_size)].push(instance.to_raw());
+        }
+
+        self.lod_instance_ranges.clear();
+        let mut start = 0;
+        for bucket in &buckets {
+            self.lod_instance_ranges.push(start..start + bucket.len() as u32);
+            start += bucket.len() as u32;
+        }
+
+        buckets.concat()
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, assets: &AssetManager) {
         self.object_rotation += cgmath::Deg(0.0);
 
         self.camera_resources.camera_controller.update_camera(&mut self.camera_resources.camera);
@@ -218,45 +826,100 @@ impl ColorPass {
             instance.rotation = quat_mul(amount, current);
         }
 
-        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data),);
+        if let Some(model) = assets.model(self.scene_model()) {
+            let instance_data = self.sort_instances_by_lod(model);
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data),);
+        }
         self.light_resources.update(queue);
+        self.skybox.update(queue, &self.camera_resources.camera);
     }
 
-    pub fn render(&self, view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder, depth_texture: &Texture) {
+    pub fn render(
+        &self,
+        target: &RenderTarget,
+        encoder: &mut wgpu::CommandEncoder,
+        assets: &AssetManager,
+    ) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(self.clear_color),
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
+            color_attachments: &[target.color_attachment(wgpu::LoadOp::Clear(self.clear_color))],
+            depth_stencil_attachment: target.depth_attachment(wgpu::LoadOp::Clear(1.0)),
         });
 
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.draw_model_instanced(
-            &self.model,
-            0..self.instances.len() as u32,
-            &self.camera_resources.camera_bind_group,
-            &self.light_resources.light_bind_group
-        );
-        render_pass.set_pipeline(&self.light_render_pipeline);
-        render_pass.draw_light_model(
-            &self.model,
-            &self.camera_resources.camera_bind_group,
-            &self.light_resources.light_bind_group,
-        );
+        if let Some(model) = assets.model(self.scene_model()) {
+            let material_array = self.material_array.as_ref().filter(|_| self.use_texture_array);
+            let shaded = matches!(self.polygon_view, PolygonView::Shaded | PolygonView::WireframeOverShaded);
+            let debug_pipeline = self.debug_pipelines
+                .iter()
+                .find(|(view, _)| *view == self.polygon_view)
+                .map(|(_, pipeline)| pipeline);
+            let barycentric = self.wireframe_method == WireframeMethod::Barycentric
+                && self.polygon_view != PolygonView::Points;
+
+            render_pass.set_bind_group(3, &self.ibl.bind_group, &[]);
+            for (lod, instances) in self.lod_instance_ranges.iter().enumerate() {
+                if instances.is_empty() {
+                    continue;
+                }
+                if shaded {
+                    match material_array {
+                        Some(material_array) => {
+                            render_pass.set_pipeline(&self.array_render_pipeline);
+                            render_pass.draw_model_array_lod_instanced(
+                                model,
+                                material_array,
+                                lod,
+                                instances.clone(),
+                                &self.camera_resources.camera_bind_group,
+                                &self.light_resources.light_bind_group
+                            );
+                        }
+                        None => {
+                            render_pass.set_pipeline(&self.render_pipeline);
+                            render_pass.draw_model_lod_instanced(
+                                model,
+                                lod,
+                                instances.clone(),
+                                &self.camera_resources.camera_bind_group,
+                                &self.light_resources.light_bind_group
+                            );
+                        }
+                    }
+                }
+                // Drawn after the shaded pass so the overlay can depth test against it
+                if let Some(debug_pipeline) = debug_pipeline {
+                    render_pass.set_pipeline(debug_pipeline);
+                    if barycentric {
+                        render_pass.draw_model_barycentric_lod_instanced(
+                            model,
+                            lod,
+                            instances.clone(),
+                            &self.camera_resources.camera_bind_group,
+                            &self.light_resources.light_bind_group
+                        );
+                    } else {
+                        render_pass.draw_model_lod_instanced(
+                            model,
+                            lod,
+                            instances.clone(),
+                            &self.camera_resources.camera_bind_group,
+                            &self.light_resources.light_bind_group
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(light_model) = assets.model(self.light_model) {
+            render_pass.set_pipeline(&self.light_render_pipeline);
+            render_pass.draw_light_model(
+                light_model,
+                &self.camera_resources.camera_bind_group,
+                &self.light_resources.light_bind_group,
+            );
+        }
+        if self.background == Background::Skybox {
+            self.skybox.draw(&mut render_pass);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,358 @@
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use wgpu::TextureFormat as F;
+
+// Block compressed data as stored in a KTX2 or DDS file. Levels start with the
+// full size image and are laid out row by row of blocks, like wgpu expects.
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+// Optional features for uploading blocks directly, files in formats the
+// device lacks are decoded on the CPU
+pub const FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR);
+
+const KTX2_MAGIC: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(&KTX2_MAGIC) || bytes.starts_with(&DDS_MAGIC)
+}
+
+pub fn parse(bytes: &[u8]) -> anyhow::Result<CompressedImage> {
+    if bytes.starts_with(&KTX2_MAGIC) {
+        parse_ktx2(bytes)
+    } else {
+        parse_dds(bytes)
+    }
+}
+
+// Only plain 2D textures, supercompressed (Basis, zstd) files aren't supported
+fn parse_ktx2(bytes: &[u8]) -> anyhow::Result<CompressedImage> {
+    let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("Invalid KTX2 file: {:?}", e))?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        anyhow::bail!("KTX2 supercompression {:?} isn't supported", scheme);
+    }
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        anyhow::bail!("Only 2D KTX2 textures are supported");
+    }
+    let format = header.format
+        .and_then(ktx2_format)
+        .ok_or_else(|| anyhow!("Unsupported KTX2 format {:?}", header.format))?;
+
+    Ok(CompressedImage {
+        format,
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        levels: reader.levels().map(|level| level.to_vec()).collect(),
+    })
+}
+
+fn parse_dds(bytes: &[u8]) -> anyhow::Result<CompressedImage> {
+    let dds = ddsfile::Dds::read(Cursor::new(bytes)).map_err(|e| anyhow!("Invalid DDS file: {:?}", e))?;
+    if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+        anyhow::bail!("Only 2D DDS textures are supported");
+    }
+    // For files without a DX10 header ddsfile guesses sRGB DXGI formats from
+    // the DXT four character codes, d3d_format has to come first for those
+    let legacy = dds.header10.is_none().then(|| dds.get_d3d_format().and_then(d3d_format)).flatten();
+    let format = legacy
+        .or_else(|| dds.get_dxgi_format().and_then(dxgi_format))
+        .ok_or_else(|| anyhow!("Unsupported DDS format {:?}", dds.get_dxgi_format().map(|f| format!("{:?}", f)).or_else(|| dds.get_d3d_format().map(|f| format!("{:?}", f)))))?;
+
+    // The levels of the first layer are stored back to back
+    let (width, height) = (dds.get_width(), dds.get_height());
+    let data = dds.get_data(0).map_err(|e| anyhow!("Invalid DDS file: {:?}", e))?;
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    for level in 0..dds.get_num_mipmap_levels().max(1) {
+        let size = level_size(format, width, height, level).2;
+        let level_data = data
+            .get(offset..offset + size)
+            .ok_or_else(|| anyhow!("DDS file is truncated at mip level {}", level))?;
+        levels.push(level_data.to_vec());
+        offset += size;
+    }
+
+    Ok(CompressedImage { format, width, height, levels })
+}
+
+// Blocks across, blocks down and bytes of a mip level
+pub fn level_size(format: wgpu::TextureFormat, width: u32, height: u32, level: u32) -> (u32, u32, usize) {
+    let info = format.describe();
+    let (block_width, block_height) = (info.block_dimensions.0 as u32, info.block_dimensions.1 as u32);
+    let blocks_x = (width >> level).max(1).div_ceil(block_width);
+    let blocks_y = (height >> level).max(1).div_ceil(block_height);
+    (blocks_x, blocks_y, (blocks_x * blocks_y) as usize * info.block_size as usize)
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format;
+    Some(match format {
+        Format::BC1_RGBA_UNORM_BLOCK | Format::BC1_RGB_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        Format::BC1_RGBA_SRGB_BLOCK | Format::BC1_RGB_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        Format::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        Format::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        Format::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        Format::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => F::Bc6hRgbSfloat,
+        Format::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        Format::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        Format::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        Format::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        Format::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        Format::ASTC_4x4_UNORM_BLOCK => F::Astc4x4RgbaUnorm,
+        Format::ASTC_4x4_SRGB_BLOCK => F::Astc4x4RgbaUnormSrgb,
+        Format::ASTC_5x4_UNORM_BLOCK => F::Astc5x4RgbaUnorm,
+        Format::ASTC_5x4_SRGB_BLOCK => F::Astc5x4RgbaUnormSrgb,
+        Format::ASTC_5x5_UNORM_BLOCK => F::Astc5x5RgbaUnorm,
+        Format::ASTC_5x5_SRGB_BLOCK => F::Astc5x5RgbaUnormSrgb,
+        Format::ASTC_6x5_UNORM_BLOCK => F::Astc6x5RgbaUnorm,
+        Format::ASTC_6x5_SRGB_BLOCK => F::Astc6x5RgbaUnormSrgb,
+        Format::ASTC_6x6_UNORM_BLOCK => F::Astc6x6RgbaUnorm,
+        Format::ASTC_6x6_SRGB_BLOCK => F::Astc6x6RgbaUnormSrgb,
+        Format::ASTC_8x5_UNORM_BLOCK => F::Astc8x5RgbaUnorm,
+        Format::ASTC_8x5_SRGB_BLOCK => F::Astc8x5RgbaUnormSrgb,
+        Format::ASTC_8x6_UNORM_BLOCK => F::Astc8x6RgbaUnorm,
+        Format::ASTC_8x6_SRGB_BLOCK => F::Astc8x6RgbaUnormSrgb,
+        Format::ASTC_8x8_UNORM_BLOCK => F::Astc8x8RgbaUnorm,
+        Format::ASTC_8x8_SRGB_BLOCK => F::Astc8x8RgbaUnormSrgb,
+        Format::ASTC_10x5_UNORM_BLOCK => F::Astc10x5RgbaUnorm,
+        Format::ASTC_10x5_SRGB_BLOCK => F::Astc10x5RgbaUnormSrgb,
+        Format::ASTC_10x6_UNORM_BLOCK => F::Astc10x6RgbaUnorm,
+        Format::ASTC_10x6_SRGB_BLOCK => F::Astc10x6RgbaUnormSrgb,
+        Format::ASTC_10x8_UNORM_BLOCK => F::Astc10x8RgbaUnorm,
+        Format::ASTC_10x8_SRGB_BLOCK => F::Astc10x8RgbaUnormSrgb,
+        Format::ASTC_10x10_UNORM_BLOCK => F::Astc10x10RgbaUnorm,
+        Format::ASTC_10x10_SRGB_BLOCK => F::Astc10x10RgbaUnormSrgb,
+        Format::ASTC_12x10_UNORM_BLOCK => F::Astc12x10RgbaUnorm,
+        Format::ASTC_12x10_SRGB_BLOCK => F::Astc12x10RgbaUnormSrgb,
+        Format::ASTC_12x12_UNORM_BLOCK => F::Astc12x12RgbaUnorm,
+        Format::ASTC_12x12_SRGB_BLOCK => F::Astc12x12RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat;
+    Some(match format {
+        DxgiFormat::BC1_UNorm => F::Bc1RgbaUnorm,
+        DxgiFormat::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        DxgiFormat::BC2_UNorm => F::Bc2RgbaUnorm,
+        DxgiFormat::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        DxgiFormat::BC3_UNorm => F::Bc3RgbaUnorm,
+        DxgiFormat::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        DxgiFormat::BC4_UNorm => F::Bc4RUnorm,
+        DxgiFormat::BC4_SNorm => F::Bc4RSnorm,
+        DxgiFormat::BC5_UNorm => F::Bc5RgUnorm,
+        DxgiFormat::BC5_SNorm => F::Bc5RgSnorm,
+        DxgiFormat::BC6H_UF16 => F::Bc6hRgbUfloat,
+        DxgiFormat::BC6H_SF16 => F::Bc6hRgbSfloat,
+        DxgiFormat::BC7_UNorm => F::Bc7RgbaUnorm,
+        DxgiFormat::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+// Files without a DX10 header, DXT2 and DXT4 only differ by premultiplied alpha
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::D3DFormat;
+    Some(match format {
+        D3DFormat::DXT1 => F::Bc1RgbaUnorm,
+        D3DFormat::DXT2 | D3DFormat::DXT3 => F::Bc2RgbaUnorm,
+        D3DFormat::DXT4 | D3DFormat::DXT5 => F::Bc3RgbaUnorm,
+        _ => return None,
+    })
+}
+
+// CPU fallback for adapters without the needed texture compression feature.
+// Only the first level is decoded, the rest of the chain is rebuilt from it.
+// HDR (BC6H) and signed formats end up as 8 bit unsigned color.
+pub fn decode(image: &CompressedImage) -> anyhow::Result<image::RgbaImage> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let data = image.levels.first().ok_or_else(|| anyhow!("Compressed image has no levels"))?;
+    let mut pixels = vec![0u32; width * height];
+
+    let info = image.format.describe();
+    let result = match image.format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => texture2ddecoder::decode_bc1(data, width, height, &mut pixels),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => texture2ddecoder::decode_bc2(data, width, height, &mut pixels),
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => texture2ddecoder::decode_bc3(data, width, height, &mut pixels),
+        F::Bc4RUnorm | F::Bc4RSnorm => texture2ddecoder::decode_bc4(data, width, height, &mut pixels),
+        F::Bc5RgUnorm | F::Bc5RgSnorm => texture2ddecoder::decode_bc5(data, width, height, &mut pixels),
+        F::Bc6hRgbUfloat => texture2ddecoder::decode_bc6_unsigned(data, width, height, &mut pixels),
+        F::Bc6hRgbSfloat => texture2ddecoder::decode_bc6_signed(data, width, height, &mut pixels),
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => texture2ddecoder::decode_bc7(data, width, height, &mut pixels),
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => texture2ddecoder::decode_etc2_rgb(data, width, height, &mut pixels),
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => texture2ddecoder::decode_etc2_rgba1(data, width, height, &mut pixels),
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => texture2ddecoder::decode_etc2_rgba8(data, width, height, &mut pixels),
+        F::EacR11Unorm => texture2ddecoder::decode_eacr(data, width, height, &mut pixels),
+        F::EacR11Snorm => texture2ddecoder::decode_eacr_signed(data, width, height, &mut pixels),
+        F::EacRg11Unorm => texture2ddecoder::decode_eacrg(data, width, height, &mut pixels),
+        _ if info.required_features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR) => {
+            let (block_width, block_height) = info.block_dimensions;
+            texture2ddecoder::decode_astc(data, width, height, block_width as usize, block_height as usize, &mut pixels)
+        }
+        format => anyhow::bail!("No CPU decoder for {:?}", format),
+    };
+    result.map_err(|e| anyhow!("Failed to decode {:?}: {}", image.format, e))?;
+
+    // The decoder writes BGRA
+    let rgba = pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [b, g, r, a] = pixel.to_le_bytes();
+            [r, g, b, a]
+        })
+        .collect();
+    Ok(image::RgbaImage::from_raw(image.width, image.height, rgba).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A KTX2 file without data format descriptor or key/value data
+    fn ktx2_file(vk_format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let index_end = 80 + levels.len() * 24;
+        let mut bytes = KTX2_MAGIC.to_vec();
+        // Format, type size, size, depth, layers, faces, levels, supercompression
+        for value in [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        // Data format descriptor and key/value data offsets and lengths
+        bytes.extend_from_slice(&[0; 16]);
+        // Supercompression global data offset and length
+        bytes.extend_from_slice(&[0; 16]);
+
+        let mut offset = index_end;
+        for level in levels {
+            for value in [offset, level.len(), level.len()] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        for level in levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    #[test]
+    fn level_size_rounds_up_to_whole_blocks() {
+        assert_eq!(level_size(F::Bc1RgbaUnorm, 8, 8, 0), (2, 2, 32));
+        assert_eq!(level_size(F::Bc1RgbaUnorm, 8, 8, 1), (1, 1, 8));
+        // Below one block every level still takes a whole one
+        assert_eq!(level_size(F::Bc1RgbaUnorm, 8, 8, 3), (1, 1, 8));
+        assert_eq!(level_size(F::Bc7RgbaUnorm, 10, 6, 0), (3, 2, 6 * 16));
+        assert_eq!(level_size(F::Bc7RgbaUnorm, 10, 6, 1), (2, 1, 2 * 16));
+        assert_eq!(level_size(F::Astc5x4RgbaUnorm, 12, 12, 0), (3, 3, 9 * 16));
+        assert_eq!(level_size(F::Rgba8Unorm, 7, 3, 1), (3, 1, 12));
+    }
+
+    #[test]
+    fn parses_ktx2_levels_and_format() {
+        // VK_FORMAT_BC1_RGBA_SRGB_BLOCK
+        let levels = vec![vec![1; 32], vec![2; 8]];
+        let bytes = ktx2_file(134, 8, 8, &levels);
+        assert!(is_container(&bytes));
+
+        let image = parse(&bytes).unwrap();
+        assert_eq!(image.format, F::Bc1RgbaUnormSrgb);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.levels, levels);
+    }
+
+    #[test]
+    fn rejects_unsupported_ktx2_files() {
+        // VK_FORMAT_R8G8B8A8_UNORM isn't block compressed
+        assert!(parse(&ktx2_file(37, 4, 4, &[vec![0; 64]])).is_err());
+
+        let mut cube = ktx2_file(131, 4, 4, &[vec![0; 8]]);
+        // Six faces
+        cube[36..40].copy_from_slice(&6u32.to_le_bytes());
+        assert!(parse(&cube).is_err());
+
+        let bytes = ktx2_file(131, 8, 8, &[vec![0; 32]]);
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn parses_dds_levels_and_format() {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 8,
+            width: 16,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC7_UNorm_sRGB,
+            mipmap_levels: Some(3),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        }).unwrap();
+        for (i, byte) in dds.data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        assert!(is_container(&bytes));
+
+        let image = parse(&bytes).unwrap();
+        assert_eq!(image.format, F::Bc7RgbaUnormSrgb);
+        assert_eq!((image.width, image.height), (16, 8));
+        // 4x2, 2x1 and 1x1 blocks of 16 bytes, back to back
+        assert_eq!(image.levels.iter().map(|level| level.len()).collect::<Vec<_>>(), [128, 32, 16]);
+        assert_eq!(image.levels[1][0], 128);
+        assert_eq!(image.levels[2][0], 160);
+    }
+
+    #[test]
+    fn parses_legacy_dds_formats() {
+        let dds = ddsfile::Dds::new_d3d(ddsfile::NewD3dParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: ddsfile::D3DFormat::DXT5,
+            mipmap_levels: None,
+            caps2: None,
+        }).unwrap();
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+
+        let image = parse(&bytes).unwrap();
+        assert_eq!(image.format, F::Bc3RgbaUnorm);
+        assert_eq!(image.levels.len(), 1);
+        assert_eq!(image.levels[0].len(), 16);
+    }
+
+    #[test]
+    fn decodes_a_solid_bc1_block() {
+        // Both endpoints pure red in RGB565, every index picks color 0
+        let block = [0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0];
+        let image = CompressedImage { format: F::Bc1RgbaUnorm, width: 4, height: 4, levels: vec![block.to_vec()] };
+
+        let rgba = decode(&image).unwrap();
+        assert_eq!(rgba.dimensions(), (4, 4));
+        assert!(rgba.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::*;
+use cgmath::{InnerSpace, Vector3};
+use image::{DynamicImage, GenericImageView, Rgba, Rgba32FImage};
+
+// Layer order of a cube texture: +X, -X, +Y, -Y, +Z, -Z
+pub const FACE_COUNT: usize = 6;
+
+// Direction through the texel at (u, v) of a face, both in -1..1 with v
+// pointing down the image, as the hardware picks faces when sampling
+pub fn face_direction(face: usize, u: f32, v: f32) -> Vector3<f32> {
+    let direction = match face {
+        0 => Vector3::new(1.0, -v, -u),
+        1 => Vector3::new(-1.0, -v, u),
+        2 => Vector3::new(u, 1.0, v),
+        3 => Vector3::new(u, -1.0, -v),
+        4 => Vector3::new(u, -v, 1.0),
+        _ => Vector3::new(-u, -v, -1.0),
+    };
+    direction.normalize()
+}
+
+// Renders every face by looking up direction(face, u, v) for each texel center
+fn build_faces<F>(face_size: u32, mut direction_color: F) -> Vec<DynamicImage>
+where
+    F: FnMut(Vector3<f32>) -> Rgba<f32>,
+{
+    (0..FACE_COUNT)
+        .map(|face| {
+            let img = Rgba32FImage::from_fn(face_size, face_size, |x, y| {
+                let u = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                direction_color(face_direction(face, u, v))
+            });
+            DynamicImage::ImageRgba32F(img)
+        })
+        .collect()
+}
+
+// Resamples a latitude/longitude panorama into six faces. Float panoramas
+// stay float, others are converted back to 8 bit.
+pub fn equirectangular_to_faces(img: &DynamicImage, face_size: u32) -> Result<Vec<DynamicImage>> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        bail!("Panorama is empty");
+    }
+    let source = img.to_rgba32f();
+
+    let faces = build_faces(face_size, |direction| {
+        let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * std::f32::consts::PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        sample_bilinear(&source, u, v)
+    });
+
+    Ok(match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => faces,
+        _ => faces.into_iter().map(|face| DynamicImage::ImageRgba8(face.to_rgba8())).collect(),
+    })
+}
+
+// Wraps horizontally and clamps vertically, like a panorama should
+fn sample_bilinear(img: &Rgba32FImage, u: f32, v: f32) -> Rgba<f32> {
+    let (width, height) = img.dimensions();
+    let x = u * width as f32 - 0.5;
+    let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as u32).min(height - 1);
+        img.get_pixel(x, y).0
+    };
+    let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+    let mut out = [0.0; 4];
+    for i in 0..4 {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        out[i] = top + (bottom - top) * fy;
+    }
+    Rgba(out)
+}
+
+// Simple sky used until a skybox is loaded, or when none is configured.
+// Colors are linear.
+pub fn gradient_faces(face_size: u32, zenith: [f32; 3], horizon: [f32; 3], ground: [f32; 3]) -> Vec<DynamicImage> {
+    let mix = |a: [f32; 3], b: [f32; 3], t: f32| {
+        Rgba([a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t, 1.0])
+    };
+    build_faces(face_size, |direction| {
+        if direction.y >= 0.0 {
+            mix(horizon, zenith, direction.y.powf(0.5))
+        } else {
+            mix(horizon, ground, (-direction.y).powf(0.25))
+        }
+    })
+}
+
+// Six separate face images, all square and the same size
+pub fn check_faces(faces: &[DynamicImage]) -> Result<u32> {
+    if faces.len() != FACE_COUNT {
+        bail!("A cubemap needs {} faces, got {}", FACE_COUNT, faces.len());
+    }
+    let (width, height) = faces[0].dimensions();
+    if width != height {
+        bail!("Cubemap faces have to be square, got {}x{}", width, height);
+    }
+    if faces.iter().any(|face| face.dimensions() != (width, height)) {
+        bail!("Cubemap faces have different sizes");
+    }
+    Ok(width)
+}
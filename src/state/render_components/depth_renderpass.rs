@@ -1,5 +1,6 @@
 use wgpu::util::DeviceExt;
 
+use super::sampler::SamplerCache;
 use super::texture::Texture;
 use super::vertex::Vertex;
 
@@ -35,8 +36,8 @@ pub struct DepthPass {
 }
 
 impl DepthPass {
-    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
-        let texture = Texture::create_depth_texture(device, config, "depth_texture");
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, samplers: &mut SamplerCache) -> Self {
+        let texture = Self::create_texture(device, config, samplers);
 
         let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Depth Pass Layout"),
@@ -152,8 +153,21 @@ impl DepthPass {
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
-        self.texture = Texture::create_depth_texture(device, config, "depth_texture");
+    fn create_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, samplers: &mut SamplerCache) -> Texture {
+        Texture::create_render_target(
+            device,
+            "depth_texture",
+            config.width,
+            config.height,
+            Texture::DEPTH_FORMAT,
+            1,
+            wgpu::TextureUsages::TEXTURE_BINDING,
+            samplers,
+        )
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, samplers: &mut SamplerCache) {
+        self.texture = Self::create_texture(device, config, samplers);
         self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.layout,
             entries: &[
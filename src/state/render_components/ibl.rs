@@ -0,0 +1,340 @@
+use std::sync::Arc;
+
+use super::cubemap;
+use super::pipeline_cache::PipelineCache;
+use super::render_pipeline::RenderPipelineBuilder;
+use super::sampler::{SamplerCache, SamplerDesc};
+use super::texture::Texture;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTER_SIZE: u32 = 128;
+// Roughness 0, 0.25, .. 1, shader.wgsl samples up to level PREFILTER_MIP_LEVELS - 1
+const PREFILTER_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+// The irradiance map and every level of the prefiltered map
+const TARGET_COUNT: u64 = 1 + PREFILTER_MIP_LEVELS as u64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct IblParams {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    source_lod: f32,
+}
+
+// Image based lighting for the color pass: diffuse irradiance and a specular
+// mip chain prefiltered by roughness, both computed from the environment
+// cubemap, plus the BRDF lookup table of the split sum approximation
+pub struct Ibl {
+    irradiance: wgpu::Texture,
+    prefiltered: wgpu::Texture,
+    sampler: Arc<wgpu::Sampler>,
+    params_layout: wgpu::BindGroupLayout,
+    // One IblParams per face of every target, params_stride bytes apart
+    params_buffer: wgpu::Buffer,
+    params_stride: u64,
+    irradiance_pipeline: Arc<wgpu::RenderPipeline>,
+    prefilter_pipeline: Arc<wgpu::RenderPipeline>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Ibl {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Texture,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+    ) -> Self {
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<IblParams>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("ibl_params_bind_group_layout"),
+        });
+        let params_pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&params_layout],
+            push_constant_ranges: &[],
+        }));
+        let irradiance_pipeline = Self::create_pipeline(device, pipelines, &params_pipeline_layout, "fs_irradiance", MAP_FORMAT);
+        let prefilter_pipeline = Self::create_pipeline(device, pipelines, &params_pipeline_layout, "fs_prefilter", MAP_FORMAT);
+
+        let irradiance = Self::create_cube_target(device, "Irradiance Map", IRRADIANCE_SIZE, 1);
+        let prefiltered = Self::create_cube_target(device, "Prefiltered Environment", PREFILTER_SIZE, PREFILTER_MIP_LEVELS);
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BRDF_LUT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        // The LUT doesn't depend on the environment, so it's only drawn once
+        let empty_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BRDF LUT Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        }));
+        let brdf_pipeline = Self::create_pipeline(device, pipelines, &empty_layout, "fs_brdf", BRDF_LUT_FORMAT);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        {
+            let view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut render_pass = Self::begin_target_pass(&mut encoder, &view);
+            render_pass.set_pipeline(&brdf_pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let sampler = samplers.get(device, &SamplerDesc::default());
+
+        // Every draw of an update reads its own slice of one buffer
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let params_stride = (std::mem::size_of::<IblParams>() as u64).div_ceil(alignment) * alignment;
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("IBL Params"),
+            size: params_stride * TARGET_COUNT * cubemap::FACE_COUNT as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                Self::texture_entry(0, wgpu::TextureViewDimension::Cube),
+                Self::texture_entry(1, wgpu::TextureViewDimension::Cube),
+                Self::texture_entry(2, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("ibl_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &irradiance, &prefiltered, &brdf_lut, &sampler);
+
+        let ibl = Self {
+            irradiance,
+            prefiltered,
+            sampler,
+            params_layout,
+            params_buffer,
+            params_stride,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            bind_group_layout,
+            bind_group,
+        };
+        ibl.update(device, queue, environment);
+        ibl
+    }
+
+    fn texture_entry(binding: u32, view_dimension: wgpu::TextureViewDimension) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        irradiance: &wgpu::Texture,
+        prefiltered: &wgpu::Texture,
+        brdf_lut: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        let cube_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let irradiance_view = cube_view(irradiance);
+        let prefiltered_view = cube_view(prefiltered);
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&irradiance_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&prefiltered_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("ibl_bind_group"),
+        })
+    }
+
+    fn create_cube_target(device: &wgpu::Device, label: &str, size: u32, mip_level_count: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: cubemap::FACE_COUNT as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MAP_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &Arc<wgpu::PipelineLayout>,
+        fragment_entry_point: &str,
+        format: wgpu::TextureFormat,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let builder = RenderPipelineBuilder::new()
+            .label(fragment_entry_point)
+            .layout(layout)
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: Some("ibl.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shader/wgsl/ibl.wgsl").into()),
+            })
+            .fragment_entry_point(fragment_entry_point)
+            .color_format(format)
+            .blend(None)
+            .cull_mode(None);
+        pipelines.get(device, builder)
+    }
+
+    fn begin_target_pass<'a>(encoder: &'a mut wgpu::CommandEncoder, view: &'a wgpu::TextureView) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("IBL Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        })
+    }
+
+    // Recomputes the irradiance and prefiltered maps, needed whenever the
+    // environment changes. environment has to be a cube texture with mips.
+    pub fn update(&self, device: &wgpu::Device, queue: &wgpu::Queue, environment: &Texture) {
+        let source_size = environment.size.width;
+        // Integrate the irradiance from a level about as large as the result,
+        // the cosine lobe is too wide to need more detail
+        let source_lod = (source_size as f32 / IRRADIANCE_SIZE as f32).log2().max(0.0);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.params_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.params_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<IblParams>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("ibl_params_bind_group"),
+        });
+
+        let targets = std::iter::once((&self.irradiance, &self.irradiance_pipeline, 0, 0.0))
+            .chain((0..PREFILTER_MIP_LEVELS).map(|mip| {
+                let roughness = mip as f32 / (PREFILTER_MIP_LEVELS - 1) as f32;
+                (&self.prefiltered, &self.prefilter_pipeline, mip, roughness)
+            }));
+        let faces = targets.flat_map(|target| (0..cubemap::FACE_COUNT as u32).map(move |face| (target, face)));
+        for (draw, ((texture, pipeline, mip_level, roughness), face)) in faces.enumerate() {
+            let offset = draw as u64 * self.params_stride;
+            let params = IblParams { face, roughness, source_size: source_size as f32, source_lod };
+            queue.write_buffer(&self.params_buffer, offset, bytemuck::cast_slice(&[params]));
+
+            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("IBL Face View"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                base_array_layer: face,
+                array_layer_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            });
+
+            let mut render_pass = Self::begin_target_pass(&mut encoder, &view);
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[offset as u32]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(Some(encoder.finish()));
+    }
+}
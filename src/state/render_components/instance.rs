@@ -5,6 +5,8 @@ pub const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    // Layer of the material array, only read by the texture array pipeline
+    pub texture_layer: u32,
 }
 
 impl Instance {
@@ -12,6 +14,7 @@ impl Instance {
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
             normal: cgmath::Matrix3::from(self.rotation).into(),
+            texture_layer: self.texture_layer,
         }
     }
 }
@@ -21,6 +24,7 @@ impl Instance {
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    texture_layer: u32,
 }
 
 impl InstanceRaw {
@@ -75,6 +79,11 @@ impl InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
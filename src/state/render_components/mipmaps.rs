@@ -0,0 +1,268 @@
+use image::{ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+
+use super::pipeline_cache::PipelineCache;
+use super::render_pipeline::RenderPipelineBuilder;
+
+// Number of levels in a full chain down to 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipmapMode {
+    Gpu,
+    Cpu,
+}
+
+impl MipmapMode {
+    // MIPMAPS=cpu builds the chain on the CPU, e.g. to compare against the GPU path
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("MIPMAPS").ok()?.to_lowercase().as_str() {
+            "gpu" => Some(MipmapMode::Gpu),
+            "cpu" => Some(MipmapMode::Cpu),
+            _ => None,
+        }
+    }
+}
+
+// Fills mip levels 1.. of texture by drawing each level into the next one with
+// a linear sampler. Needs TEXTURE_BINDING and RENDER_ATTACHMENT usage, level 0
+// has to be written already.
+pub fn generate_mipmaps(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pipelines: &mut PipelineCache,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    mip_level_count: u32,
+) {
+    if mip_level_count <= 1 {
+        return;
+    }
+
+    let builder = RenderPipelineBuilder::new()
+        .label("Mipmap Pipeline")
+        .shader(wgpu::ShaderModuleDescriptor {
+            label: Some("blit.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shader/wgsl/blit.wgsl").into()),
+        })
+        .color_format(format)
+        .blend(None)
+        .cull_mode(None);
+    let pipeline = pipelines.get(device, builder);
+    let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Mipmap Sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    let views = (0..mip_level_count)
+        .map(|mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Mip View"),
+                base_mip_level: mip,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    for target in 1..views.len() {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[target - 1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: None,
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &views[target],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    queue.submit(Some(encoder.finish()));
+}
+
+// Levels 1.. of the chain, each a 2x2 box filter of the previous one. With
+// srgb set color is averaged in linear space to match what the GPU does with
+// sRGB views.
+pub fn generate_mipmaps_cpu(image: &RgbaImage, srgb: bool) -> Vec<RgbaImage> {
+    mip_chain(
+        image,
+        |value, channel| {
+            if srgb && channel < 3 {
+                srgb_to_linear(value)
+            } else {
+                value as f32 / 255.0
+            }
+        },
+        |value, channel| {
+            let value = if srgb && channel < 3 { linear_to_srgb(value) } else { value };
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        },
+    )
+}
+
+// Float images are linear already
+pub fn generate_mipmaps_cpu_f32(image: &Rgba32FImage) -> Vec<Rgba32FImage> {
+    mip_chain(image, |value, _| value, |value, _| value)
+}
+
+fn mip_chain<T: image::Primitive + 'static>(
+    image: &ImageBuffer<Rgba<T>, Vec<T>>,
+    to_linear: impl Fn(T, usize) -> f32,
+    from_linear: impl Fn(f32, usize) -> T,
+) -> Vec<ImageBuffer<Rgba<T>, Vec<T>>>
+where
+    Rgba<T>: image::Pixel<Subpixel = T>,
+{
+    let mut levels: Vec<ImageBuffer<Rgba<T>, Vec<T>>> = Vec::new();
+    loop {
+        let previous = levels.last().unwrap_or(image);
+        let (width, height) = previous.dimensions();
+        if width <= 1 && height <= 1 {
+            break;
+        }
+
+        let next = ImageBuffer::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+            let mut sum = [0.0f32; 4];
+            let mut count = 0.0;
+            for sy in 2 * y..(2 * y + 2).min(height) {
+                for sx in 2 * x..(2 * x + 2).min(width) {
+                    let pixel = previous.get_pixel(sx, sy);
+                    for c in 0..4 {
+                        sum[c] += to_linear(pixel[c], c);
+                    }
+                    count += 1.0;
+                }
+            }
+            Rgba([0, 1, 2, 3].map(|c| from_linear(sum[c] / count, c)))
+        });
+        levels.push(next);
+    }
+    levels
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_count_goes_down_to_one_pixel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(2, 2), 2);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(255, 255), 8);
+        // The longer side decides, the shorter one stays at 1
+        assert_eq!(mip_level_count(512, 4), 10);
+        assert_eq!(mip_level_count(3, 1000), 10);
+        assert_eq!(mip_level_count(0, 0), 1);
+    }
+
+    #[test]
+    fn cpu_chain_has_a_level_per_mip() {
+        for (width, height) in [(4, 4), (5, 3), (16, 1), (1, 7)] {
+            let levels = generate_mipmaps_cpu(&RgbaImage::new(width, height), true);
+            assert_eq!(levels.len() + 1, mip_level_count(width, height) as usize);
+            assert_eq!(levels.last().unwrap().dimensions(), (1, 1));
+        }
+    }
+
+    #[test]
+    fn srgb_averaging_happens_in_linear_space() {
+        // Black and white checkerboard, alpha is linear in either case
+        let image = RgbaImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+
+        let srgb = generate_mipmaps_cpu(&image, true);
+        assert_eq!(srgb.len(), 1);
+        // Half the light of white is 0.5 linear, about 188 in sRGB rather than 128
+        assert_eq!(*srgb[0].get_pixel(0, 0), Rgba([188, 188, 188, 128]));
+
+        let linear = generate_mipmaps_cpu(&image, false);
+        assert_eq!(*linear[0].get_pixel(0, 0), Rgba([128, 128, 128, 128]));
+    }
+
+    #[test]
+    fn srgb_chain_of_4x4_averages_each_quadrant() {
+        // One color per 2x2 quadrant, so level 1 reproduces the quadrants exactly
+        let quadrants = [[10, 200, 90, 255], [255, 0, 30, 255], [0, 0, 0, 255], [64, 128, 255, 255]];
+        let image = RgbaImage::from_fn(4, 4, |x, y| Rgba(quadrants[(y / 2 * 2 + x / 2) as usize]));
+
+        let levels = generate_mipmaps_cpu(&image, true);
+        assert_eq!(levels.len(), 2);
+        for (i, quadrant) in quadrants.iter().enumerate() {
+            assert_eq!(levels[0].get_pixel(i as u32 % 2, i as u32 / 2).0, *quadrant);
+        }
+
+        let last = levels[1].get_pixel(0, 0);
+        for c in 0..3 {
+            let linear = quadrants.iter().map(|q| srgb_to_linear(q[c])).sum::<f32>() / 4.0;
+            let expected = (linear_to_srgb(linear) * 255.0).round() as u8;
+            assert_eq!(last[c], expected);
+            // Averaging the encoded values would come out darker
+            let naive = quadrants.iter().map(|q| q[c] as u32).sum::<u32>() / 4;
+            assert!(last[c] as u32 > naive);
+        }
+        assert_eq!(last[3], 255);
+    }
+
+    #[test]
+    fn srgb_conversion_round_trips() {
+        for value in 0..=255u8 {
+            let back = (linear_to_srgb(srgb_to_linear(value)) * 255.0).round() as u8;
+            assert_eq!(back, value);
+        }
+    }
+}
@@ -1,9 +1,18 @@
 pub mod vertex;
 pub mod model;
+pub mod primitives;
 pub mod texture;
+pub mod compressed;
+pub mod cubemap;
+pub mod skybox;
+pub mod ibl;
+pub mod mipmaps;
+pub mod sampler;
 pub mod instance;
 pub mod camera;
 pub mod light;
 pub mod render_pipeline;
+pub mod pipeline_cache;
+pub mod render_target;
 pub mod color_renderpass;
 pub mod depth_renderpass;
\ No newline at end of file
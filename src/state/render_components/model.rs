@@ -1,6 +1,10 @@
 use std::ops::Range;
 
+use wgpu::util::DeviceExt;
+
 use super::texture::Texture;
+use super::render_pipeline::WireframeMethod;
+use crate::state::assets::Handle;
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -41,23 +45,358 @@ impl Vertex for ModelVertex {
     }
 }
 
+// Same attributes as ModelVertex, packed into 20 instead of 32 bytes.
+// Tex coords are stored as half floats, so tiled meshes keep coordinates
+// outside [0, 1], normals are stored as snorm8 with an unused fourth component.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct QuantizedModelVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [u16; 2],
+    pub normal: [i8; 4],
+}
+
+impl QuantizedModelVertex {
+    pub fn from_model_vertex(vertex: &ModelVertex) -> Self {
+        let [u, v] = vertex.tex_coords;
+        let [x, y, z] = vertex.normal;
+        Self {
+            position: vertex.position,
+            tex_coords: [half::f16::from_f32(u).to_bits(), half::f16::from_f32(v).to_bits()],
+            normal: [quantize_snorm8(x), quantize_snorm8(y), quantize_snorm8(z), 0],
+        }
+    }
+}
+
+fn quantize_snorm8(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * i8::MAX as f32).round() as i8
+}
+
+impl Vertex for QuantizedModelVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        // The shader still reads vec2/vec3 floats, the half float and snorm
+        // formats are expanded by the vertex fetch
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<QuantizedModelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float16x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: (mem::size_of::<[f32; 3]>() + mem::size_of::<[u16; 2]>()) as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Snorm8x4,
+                },
+            ],
+        }
+    }
+}
+
+// Selects which vertex struct meshes are uploaded with. All meshes drawn by
+// one pipeline have to use the same layout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModelVertexLayout {
+    Full,
+    Quantized,
+}
+
+impl ModelVertexLayout {
+    // Reads MODEL_VERTEX_LAYOUT=full|quantized, similar to WGPU_BACKEND
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("MODEL_VERTEX_LAYOUT").ok()?.to_lowercase().as_str() {
+            "full" => Some(ModelVertexLayout::Full),
+            "quantized" => Some(ModelVertexLayout::Quantized),
+            _ => None,
+        }
+    }
+
+    pub fn desc<'a>(&self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            ModelVertexLayout::Full => ModelVertex::desc(),
+            ModelVertexLayout::Quantized => QuantizedModelVertex::desc(),
+        }
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
 }
 
+// Minimum projected size (fraction of the screen height) for each LOD level,
+// anything smaller than the last entry uses the next LOD after it
+pub const LOD_SCREEN_SIZES: [f32; 3] = [0.08, 0.04, 0.02];
+
+impl Model {
+    pub fn lod_count(&self) -> usize {
+        self.meshes.iter().map(|mesh| mesh.lods.len()).max().unwrap_or(1).max(1)
+    }
+
+    pub fn select_lod(&self, screen_size: f32) -> usize {
+        let lod = LOD_SCREEN_SIZES.iter()
+            .position(|&min_size| screen_size >= min_size)
+            .unwrap_or(LOD_SCREEN_SIZES.len());
+        lod.min(self.lod_count() - 1)
+    }
+
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
+    pub diffuse_texture: Handle<Texture>,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: Handle<Texture>,
+        texture: &Texture,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Self {
+            name: String::from(name),
+            diffuse_texture,
+            bind_group,
+        }
+    }
+}
+
+// Several same sized materials packed into one array texture, so instances
+// can pick theirs by layer and share a single bind group and draw call
+pub struct MaterialArray {
+    // diffuse_textures[layer] is the texture copied into that layer
+    pub diffuse_textures: Vec<Handle<Texture>>,
+    // Read through bind_group
+    _texture: Texture,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl MaterialArray {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        textures: &[(Handle<Texture>, &Texture)],
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let layers = textures.iter().map(|(_, texture)| *texture).collect::<Vec<_>>();
+        let texture = Texture::pack_layers(device, queue, &layers, Some(name))?;
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Ok(Self {
+            diffuse_textures: textures.iter().map(|(handle, _)| *handle).collect(),
+            _texture: texture,
+            bind_group,
+        })
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.diffuse_textures.len() as u32
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
+    // The vertices of every index in order, for barycentric wireframes. Only
+    // made if the device has no line polygon mode, see WireframeMethod.
+    pub barycentric_vertex_buffer: Option<wgpu::Buffer>,
     pub num_elements: u32,
+    pub lods: Vec<Range<u32>>,
     pub material: usize,
+    pub bounds: BoundingBox,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+        layout: ModelVertexLayout,
+    ) -> Self {
+        Self::with_lods(device, name, vertices, &[indices.to_vec()], material, layout)
+    }
+
+    // All LODs index into the same vertex buffer and are stored back to back
+    // in one index buffer, starting with the full detail mesh
+    pub fn with_lods(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        lod_indices: &[Vec<u32>],
+        material: usize,
+        layout: ModelVertexLayout,
+    ) -> Self {
+        let vertex_data: Vec<u8> = match layout {
+            ModelVertexLayout::Full => bytemuck::cast_slice(vertices).to_vec(),
+            ModelVertexLayout::Quantized => {
+                let quantized = vertices.iter()
+                    .map(QuantizedModelVertex::from_model_vertex)
+                    .collect::<Vec<_>>();
+                bytemuck::cast_slice(&quantized).to_vec()
+            }
+        };
+
+        let mut lods = Vec::with_capacity(lod_indices.len());
+        let mut first_index = 0;
+        for indices in lod_indices {
+            lods.push(first_index..first_index + indices.len() as u32);
+            first_index += indices.len() as u32;
+        }
+        let indices = lod_indices.concat();
+
+        // 16 bit indices halve the index buffer as long as every vertex is addressable
+        let (index_data, index_format): (Vec<u8>, _) = if vertices.len() <= u16::MAX as usize {
+            let indices = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+            (bytemuck::cast_slice(&indices).to_vec(), wgpu::IndexFormat::Uint16)
+        } else {
+            (bytemuck::cast_slice(&indices).to_vec(), wgpu::IndexFormat::Uint32)
+        };
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: &vertex_data,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: &index_data,
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let barycentric_vertex_buffer = (WireframeMethod::for_features(device.features()) == WireframeMethod::Barycentric)
+            .then(|| {
+                let stride = vertex_data.len() / vertices.len().max(1);
+                let data = indices
+                    .iter()
+                    .flat_map(|&index| &vertex_data[index as usize * stride..(index as usize + 1) * stride])
+                    .copied()
+                    .collect::<Vec<u8>>();
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} Barycentric Vertex Buffer", name)),
+                    contents: &data,
+                    usage: wgpu::BufferUsages::VERTEX,
+                })
+            });
+
+        Self {
+            name: String::from(name),
+            vertex_buffer,
+            index_buffer,
+            index_format,
+            barycentric_vertex_buffer,
+            num_elements: lods.first().map_or(0, |lod| lod.end),
+            lods,
+            material,
+            bounds: BoundingBox::from_points(vertices.iter().map(|v| v.position)),
+        }
+    }
+
+    // Index range of the given LOD, falling back to the coarsest one available
+    pub fn lod(&self, lod: usize) -> Range<u32> {
+        match self.lods.get(lod).or_else(|| self.lods.last()) {
+            Some(range) => range.clone(),
+            None => 0..self.num_elements,
+        }
+    }
+}
+
+// Axis aligned bounding box in model space
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingBox {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl BoundingBox {
+    pub fn from_points<I: IntoIterator<Item = [f32; 3]>>(points: I) -> Self {
+        let mut min = cgmath::Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = cgmath::Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for [x, y, z] in points {
+            min = cgmath::Point3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = cgmath::Point3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+        }
+        // An empty mesh collapses to the origin instead of an inverted box
+        if min.x > max.x {
+            min = cgmath::Point3::new(0.0, 0.0, 0.0);
+            max = min;
+        }
+        Self { min, max }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: cgmath::Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: cgmath::Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> cgmath::Point3<f32> {
+        cgmath::EuclideanSpace::midpoint(self.min, self.max)
+    }
+
+    // Radius of the sphere around center() that encloses the box
+    pub fn radius(&self) -> f32 {
+        use cgmath::InnerSpace;
+        (self.max - self.min).magnitude() * 0.5
+    }
 }
 
 pub trait DrawModel<'a> {
@@ -76,15 +415,46 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    fn draw_mesh_lod_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_model(
         &mut self, 
         model: &'a Model, 
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_model_instanced(
+    fn draw_model_lod_instanced(
         &mut self,
         model: &'a Model,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    // Draws every mesh with the array material instead of its own, the
+    // layer comes from the instance data
+    fn draw_model_array_lod_instanced(
+        &mut self,
+        model: &'a Model,
+        material_array: &'a MaterialArray,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    // Draws the barycentric copy of the meshes without indices, meshes
+    // without one are drawn indexed
+    fn draw_model_barycentric_lod_instanced(
+        &mut self,
+        model: &'a Model,
+        lod: usize,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -118,13 +488,32 @@ where
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
+    ){
+        self.draw_mesh_lod_instanced(
+            mesh,
+            material,
+            0,
+            instances,
+            camera_bind_group,
+            light_bind_group,
+        );
+    }
+
+    fn draw_mesh_lod_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     ){
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
+        self.draw_indexed(mesh.lod(lod), 0, instances);
     }
 
     fn draw_model(
@@ -133,32 +522,85 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
-        self.draw_model_instanced(
+        self.draw_model_lod_instanced(
             model,
+            0,
             0..1,
             camera_bind_group,
             light_bind_group,
         );
     }
 
-    fn draw_model_instanced(
+    fn draw_model_lod_instanced(
         &mut self,
         model: &'b Model,
+        lod: usize,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
         for mesh in &model.meshes {
             let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(
+            self.draw_mesh_lod_instanced(
                 mesh, 
                 material, 
+                lod,
                 instances.clone(), 
                 camera_bind_group,
                 light_bind_group,
             );
         }
     }
+
+    fn draw_model_array_lod_instanced(
+        &mut self,
+        model: &'b Model,
+        material_array: &'b MaterialArray,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.set_bind_group(0, &material_array.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            self.draw_indexed(mesh.lod(lod), 0, instances.clone());
+        }
+    }
+
+    fn draw_model_barycentric_lod_instanced(
+        &mut self,
+        model: &'b Model,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            match &mesh.barycentric_vertex_buffer {
+                // LOD ranges stay valid, the copy has one vertex per index
+                Some(vertex_buffer) => {
+                    self.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    self.set_bind_group(0, &material.bind_group, &[]);
+                    self.set_bind_group(1, camera_bind_group, &[]);
+                    self.set_bind_group(2, light_bind_group, &[]);
+                    self.draw(mesh.lod(lod), instances.clone());
+                }
+                None => self.draw_mesh_lod_instanced(
+                    mesh,
+                    material,
+                    lod,
+                    instances.clone(),
+                    camera_bind_group,
+                    light_bind_group,
+                ),
+            }
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
@@ -211,7 +653,7 @@ where
         light_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(0, camera_bind_group, &[]);
         self.set_bind_group(1, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
@@ -238,3 +680,41 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantize(tex_coords: [f32; 2], normal: [f32; 3]) -> QuantizedModelVertex {
+        QuantizedModelVertex::from_model_vertex(&ModelVertex {
+            position: [1.0, 2.0, 3.0],
+            tex_coords,
+            normal,
+        })
+    }
+
+    fn tex_coords(vertex: &QuantizedModelVertex) -> [f32; 2] {
+        vertex.tex_coords.map(|t| half::f16::from_bits(t).to_f32())
+    }
+
+    #[test]
+    fn quantized_tex_coords_keep_tiling_range() {
+        let vertex = quantize([2.5, -1.0], [0.0, 0.0, 1.0]);
+        assert_eq!(tex_coords(&vertex), [2.5, -1.0]);
+        assert_eq!(vertex.position, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn quantized_tex_coords_are_close_in_the_unit_range() {
+        let vertex = quantize([0.3, 0.999], [0.0, 0.0, 1.0]);
+        for (quantized, original) in tex_coords(&vertex).iter().zip([0.3, 0.999]) {
+            assert!((quantized - original).abs() < 1.0 / 2048.0, "{} vs {}", quantized, original);
+        }
+    }
+
+    #[test]
+    fn quantized_normals_are_snorm8() {
+        let vertex = quantize([0.0, 0.0], [0.0, -1.0, 0.5]);
+        assert_eq!(vertex.normal, [0, -127, 64, 0]);
+    }
+}
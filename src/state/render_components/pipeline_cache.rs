@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::render_pipeline::{PipelineKey, RenderPipelineBuilder};
+
+struct CachedPipeline {
+    pipeline: Arc<wgpu::RenderPipeline>,
+    shader_labels: Vec<String>,
+    // Part of the key by address
+    _layout: Option<Arc<wgpu::PipelineLayout>>,
+}
+
+// Hands out one shared pipeline per distinct pipeline state, so passes asking
+// for the same state share it and switching an option back doesn't rebuild
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, CachedPipeline>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self {
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, builder: RenderPipelineBuilder) -> Arc<wgpu::RenderPipeline> {
+        self.pipelines
+            .entry(builder.key())
+            .or_insert_with(|| CachedPipeline {
+                shader_labels: builder.shader_labels(),
+                _layout: builder.pipeline_layout(),
+                pipeline: Arc::new(builder.build(device)),
+            })
+            .pipeline
+            .clone()
+    }
+
+    // Forgets the pipelines built from the shader module with this label, e.g.
+    // once its file changed. Pipelines still in use live on until they're
+    // replaced. Returns how many were dropped.
+    pub fn invalidate_shader(&mut self, label: &str) -> usize {
+        let count = self.pipelines.len();
+        self.pipelines
+            .retain(|_, cached| !cached.shader_labels.iter().any(|shader_label| shader_label == label));
+        count - self.pipelines.len()
+    }
+}
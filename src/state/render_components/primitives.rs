@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use super::model::{Material, Mesh, Model, ModelVertex, ModelVertexLayout};
+use crate::util::mesh_simplifier;
+
+// CPU side geometry of a generated primitive. All primitives are centered on
+// the origin with +Y up, use counter clockwise front faces and have
+// tex coords with v pointing down like image rows.
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn into_mesh(
+        self,
+        device: &wgpu::Device,
+        name: &str,
+        material: usize,
+        layout: ModelVertexLayout,
+    ) -> Mesh {
+        Mesh::new(device, name, &self.vertices, &self.indices, material, layout)
+    }
+
+    pub fn into_model(
+        self,
+        device: &wgpu::Device,
+        name: &str,
+        material: Material,
+        layout: ModelVertexLayout,
+    ) -> Model {
+        Model {
+            meshes: vec![self.into_mesh(device, name, 0, layout)],
+            materials: vec![material],
+        }
+    }
+
+    // Like into_model, with LODs from mesh_simplifier
+    pub fn into_lod_model(
+        self,
+        device: &wgpu::Device,
+        name: &str,
+        material: Material,
+        layout: ModelVertexLayout,
+    ) -> Model {
+        let lods = mesh_simplifier::generate_lods(&self.vertices, &self.indices);
+        Model {
+            meshes: vec![Mesh::with_lods(device, name, &self.vertices, &lods, 0, layout)],
+            materials: vec![material],
+        }
+    }
+
+    fn vertex(&mut self, position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> u32 {
+        self.vertices.push(ModelVertex { position, tex_coords, normal });
+        (self.vertices.len() - 1) as u32
+    }
+
+    // Triangles that collapse at poles or apexes are left out
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| cgmath::Vector3::from(self.vertices[i as usize].position));
+        if cgmath::InnerSpace::magnitude2((pb - pa).cross(pc - pa)) > f32::EPSILON * f32::EPSILON {
+            self.indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+}
+
+// Generated models the scene can show in place of the loaded one, sized to
+// about the same extent as the cube
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Plane,
+    UvSphere,
+    Icosphere,
+    Cylinder,
+    Cone,
+    Torus,
+    Capsule,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 7] = [
+        Shape::Plane,
+        Shape::UvSphere,
+        Shape::Icosphere,
+        Shape::Cylinder,
+        Shape::Cone,
+        Shape::Torus,
+        Shape::Capsule,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Plane => "Plane",
+            Shape::UvSphere => "UV sphere",
+            Shape::Icosphere => "Icosphere",
+            Shape::Cylinder => "Cylinder",
+            Shape::Cone => "Cone",
+            Shape::Torus => "Torus",
+            Shape::Capsule => "Capsule",
+        }
+    }
+
+    pub fn generate(&self) -> MeshData {
+        match self {
+            Shape::Plane => plane(2.0, 2.0, 8),
+            Shape::UvSphere => uv_sphere(1.0, 32, 16),
+            Shape::Icosphere => icosphere(1.0, 3),
+            Shape::Cylinder => cylinder(0.8, 2.0, 32),
+            Shape::Cone => cone(1.0, 2.0, 32),
+            Shape::Torus => torus(0.7, 0.3, 32, 16),
+            Shape::Capsule => capsule(0.6, 0.8, 32, 8),
+        }
+    }
+}
+
+// One row of a surface of revolution, revolved around the Y axis
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    // Normal in the (radial, y) plane
+    normal: [f32; 2],
+    v: f32,
+}
+
+fn lathe(data: &mut MeshData, profile: &[ProfilePoint], segments: u32) {
+    let segments = segments.max(3);
+    let first = data.vertices.len() as u32;
+
+    for point in profile {
+        for j in 0..=segments {
+            let u = j as f32 / segments as f32;
+            // The last column repeats the angle of the first one exactly, so
+            // the seam vertices only differ in their tex coords
+            let (sin, cos) = ((j % segments) as f32 / segments as f32 * 2.0 * PI).sin_cos();
+            // -sin keeps u increasing left to right when seen from outside
+            data.vertex(
+                [point.radius * cos, point.y, -point.radius * sin],
+                [u, point.v],
+                [point.normal[0] * cos, point.normal[1], -point.normal[0] * sin],
+            );
+        }
+    }
+
+    let row = segments + 1;
+    for r in 0..profile.len().saturating_sub(1) as u32 {
+        for j in 0..segments {
+            let a = first + r * row + j;
+            let b = a + 1;
+            let c = a + row;
+            let d = c + 1;
+            data.triangle(a, c, b);
+            data.triangle(b, c, d);
+        }
+    }
+}
+
+fn disk(data: &mut MeshData, radius: f32, y: f32, facing_up: bool, segments: u32) {
+    let segments = segments.max(3);
+    let normal = [0.0, if facing_up { 1.0 } else { -1.0 }, 0.0];
+    let center = data.vertex([0.0, y, 0.0], [0.5, 0.5], normal);
+
+    for j in 0..=segments {
+        let (sin, cos) = ((j % segments) as f32 / segments as f32 * 2.0 * PI).sin_cos();
+        data.vertex(
+            [radius * cos, y, -radius * sin],
+            [0.5 + 0.5 * cos, 0.5 + 0.5 * if facing_up { -sin } else { sin }],
+            normal,
+        );
+    }
+
+    for j in 0..segments {
+        let (a, b) = (center + 1 + j, center + 2 + j);
+        if facing_up {
+            data.triangle(center, a, b);
+        } else {
+            data.triangle(center, b, a);
+        }
+    }
+}
+
+// Plane in XZ facing +Y
+pub fn plane(width: f32, depth: f32, subdivisions: u32) -> MeshData {
+    let n = subdivisions.max(1);
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+
+    for j in 0..=n {
+        for i in 0..=n {
+            let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+            data.vertex([(u - 0.5) * width, 0.0, (v - 0.5) * depth], [u, v], [0.0, 1.0, 0.0]);
+        }
+    }
+
+    for j in 0..n {
+        for i in 0..n {
+            let a = j * (n + 1) + i;
+            let b = a + 1;
+            let c = a + n + 1;
+            let d = c + 1;
+            data.triangle(a, c, b);
+            data.triangle(b, c, d);
+        }
+    }
+
+    data
+}
+
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> MeshData {
+    let stacks = stacks.max(2);
+    let profile = (0..=stacks)
+        .map(|i| {
+            let v = i as f32 / stacks as f32;
+            // sin(PI) isn't quite zero, the poles have to be single points
+            let (sin, cos) = if i == stacks { (0.0, -1.0) } else { (v * PI).sin_cos() };
+            ProfilePoint { radius: radius * sin, y: radius * cos, normal: [sin, cos], v }
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+    lathe(&mut data, &profile, sectors);
+    data
+}
+
+// Subdivided icosahedron. Tex coords are a spherical projection. Triangles
+// crossing the u seam are split along it, and vertices on it are duplicated,
+// so no triangle wraps across the texture and u stays within [0, 1].
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<cgmath::Vector3<f32>> = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|&p| cgmath::InnerSpace::normalize(cgmath::Vector3::from(p)))
+    .collect();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let p = (positions[a as usize] + positions[b as usize]) * 0.5;
+                positions.push(cgmath::InnerSpace::normalize(p));
+                (positions.len() - 1) as u32
+            })
+        };
+
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+    for p in &positions {
+        // Same orientation as the lathe based shapes: u grows towards -Z
+        let u = 0.5 + (-p.z).atan2(p.x) / (2.0 * PI);
+        let v = p.y.clamp(-1.0, 1.0).acos() / PI;
+        data.vertex((p * radius).into(), [u, v], (*p).into());
+    }
+
+    // The seam is the half plane z = 0, x < 0. Near it -z > 0 maps to u close
+    // to 1 and -z < 0 to u close to 0.
+    let mut seam_copies = HashMap::new();
+    let mut seam_vertex = |data: &mut MeshData, i: u32, high: bool| -> u32 {
+        let u = if high { 1.0 } else { 0.0 };
+        if data.vertices[i as usize].tex_coords[0] == u {
+            return i;
+        }
+        *seam_copies.entry((i, high)).or_insert_with(|| {
+            let mut copy = data.vertices[i as usize];
+            copy.tex_coords[0] = u;
+            data.vertices.push(copy);
+            (data.vertices.len() - 1) as u32
+        })
+    };
+    // Where an edge crosses the seam, as a vertex for either side
+    let mut crossings = HashMap::new();
+    let mut crossing = |data: &mut MeshData, a: u32, b: u32| -> (u32, u32) {
+        *crossings.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            let (pa, pb) = (positions[a.min(b) as usize], positions[a.max(b) as usize]);
+            let t = pa.z / (pa.z - pb.z);
+            let mut p = pa + (pb - pa) * t;
+            p.z = 0.0;
+            let p = cgmath::InnerSpace::normalize(p);
+            let v = p.y.clamp(-1.0, 1.0).acos() / PI;
+            let low = data.vertex((p * radius).into(), [0.0, v], p.into());
+            let high = data.vertex((p * radius).into(), [1.0, v], p.into());
+            (low, high)
+        })
+    };
+
+    for face in &faces {
+        let us = face.map(|i| data.vertices[i as usize].tex_coords[0]);
+        let wraps = us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min) > 0.5;
+        if !wraps {
+            data.triangle(face[0], face[1], face[2]);
+            continue;
+        }
+
+        // Clip the triangle into the part on either side of the seam
+        let side = |i: u32| -positions[i as usize].z;
+        let mut low = Vec::with_capacity(4);
+        let mut high = Vec::with_capacity(4);
+        for k in 0..3 {
+            let (a, b) = (face[k], face[(k + 1) % 3]);
+            let (sa, sb) = (side(a), side(b));
+            if sa > 0.0 {
+                high.push(a);
+            } else if sa < 0.0 {
+                low.push(a);
+            } else {
+                high.push(seam_vertex(&mut data, a, true));
+                low.push(seam_vertex(&mut data, a, false));
+            }
+            if sa * sb < 0.0 {
+                let (crossing_low, crossing_high) = crossing(&mut data, a, b);
+                low.push(crossing_low);
+                high.push(crossing_high);
+            }
+        }
+        for polygon in [low, high] {
+            for k in 1..polygon.len().saturating_sub(1) {
+                data.triangle(polygon[0], polygon[k], polygon[k + 1]);
+            }
+        }
+    }
+
+    data
+}
+
+// Open or closed frustum, a cone is a frustum with a top radius of zero
+fn frustum(bottom_radius: f32, top_radius: f32, height: f32, segments: u32) -> MeshData {
+    let half = height * 0.5;
+    let slope = bottom_radius - top_radius;
+    let length = (height * height + slope * slope).sqrt().max(f32::EPSILON);
+    let normal = [height / length, slope / length];
+
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+    lathe(
+        &mut data,
+        &[
+            ProfilePoint { radius: top_radius, y: half, normal, v: 0.0 },
+            ProfilePoint { radius: bottom_radius, y: -half, normal, v: 1.0 },
+        ],
+        segments,
+    );
+    if top_radius > 0.0 {
+        disk(&mut data, top_radius, half, true, segments);
+    }
+    if bottom_radius > 0.0 {
+        disk(&mut data, bottom_radius, -half, false, segments);
+    }
+    data
+}
+
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    frustum(radius, radius, height, segments)
+}
+
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    frustum(radius, 0.0, height, segments)
+}
+
+// Torus lying in XZ, major_radius is measured to the center of the tube
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> MeshData {
+    let minor_segments = minor_segments.max(3);
+    // Walk the tube cross section from its top, over the outside, to the top again
+    let profile = (0..=minor_segments)
+        .map(|i| {
+            let v = i as f32 / minor_segments as f32;
+            // Like the columns in lathe, the last row closes the tube exactly
+            let (sin, cos) = ((i % minor_segments) as f32 / minor_segments as f32 * 2.0 * PI).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * sin,
+                y: minor_radius * cos,
+                normal: [sin, cos],
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+    lathe(&mut data, &profile, major_segments);
+    data
+}
+
+// Cylinder of the given height with a hemisphere on either end
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let half = height * 0.5;
+    // Distribute v by arc length so the texture isn't squashed on the caps
+    let total = PI * radius + height;
+
+    let mut profile = Vec::with_capacity(2 * rings as usize + 2);
+    for (offset, first_angle) in [(half, 0.0), (-half, PI * 0.5)] {
+        for i in 0..=rings {
+            let angle = first_angle + i as f32 / rings as f32 * PI * 0.5;
+            // The bottom pole has to be a single point, sin(PI) isn't quite zero
+            let (sin, cos) = if offset < 0.0 && i == rings { (0.0, -1.0) } else { angle.sin_cos() };
+            let arc = angle * radius + if offset < 0.0 { height } else { 0.0 };
+            profile.push(ProfilePoint {
+                radius: radius * sin,
+                y: offset + radius * cos,
+                normal: [sin, cos],
+                v: arc / total,
+            });
+        }
+    }
+
+    let mut data = MeshData { vertices: Vec::new(), indices: Vec::new() };
+    lathe(&mut data, &profile, segments);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    fn check(shape: Shape) {
+        let data = shape.generate();
+        let name = shape.name();
+
+        assert!(!data.indices.is_empty(), "{}", name);
+        assert_eq!(data.indices.len() % 3, 0, "{}", name);
+        assert!(data.indices.iter().all(|&i| (i as usize) < data.vertices.len()), "{}: index out of bounds", name);
+
+        for vertex in &data.vertices {
+            let length = cgmath::Vector3::from(vertex.normal).magnitude();
+            assert!((length - 1.0).abs() < 1e-4, "{}: normal {:?}", name, vertex.normal);
+            for t in vertex.tex_coords {
+                assert!((0.0..=1.0).contains(&t), "{}: tex coords {:?}", name, vertex.tex_coords);
+            }
+        }
+
+        for triangle in data.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| data.vertices[triangle[k] as usize]);
+            // Counter clockwise seen from the side the normals point to
+            let [pa, pb, pc] = [a, b, c].map(|v| cgmath::Vector3::from(v.position));
+            let face_normal = (pb - pa).cross(pc - pa);
+            let vertex_normal: cgmath::Vector3<f32> = [a, b, c].iter().map(|v| cgmath::Vector3::from(v.normal)).sum();
+            assert!(face_normal.dot(vertex_normal) > 0.0, "{}: triangle {:?} faces inwards", name, triangle);
+            // No triangle spans the texture seam
+            let us = [a, b, c].map(|v| v.tex_coords[0]);
+            let span = us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min);
+            assert!(span <= 0.5, "{}: triangle {:?} wraps around the texture", name, triangle);
+        }
+    }
+
+    #[test]
+    fn shapes_have_unit_normals_valid_tex_coords_and_indices() {
+        for shape in Shape::ALL {
+            check(shape);
+        }
+    }
+
+    #[test]
+    fn icosphere_seam_stays_closed() {
+        // Both copies of a seam vertex share the position, so welding by
+        // position must leave no open edges
+        let data = icosphere(1.0, 2);
+        let key = |i: u32| data.vertices[i as usize].position.map(|p| if p == 0.0 { 0 } else { p.to_bits() });
+        let mut edges = HashMap::new();
+        for triangle in data.indices.chunks_exact(3) {
+            for k in 0..3 {
+                *edges.entry((key(triangle[k]), key(triangle[(k + 1) % 3]))).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+}
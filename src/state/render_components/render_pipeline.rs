@@ -1,117 +1,430 @@
-pub fn create_render_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
+use std::sync::Arc;
+
+use super::render_target::RenderTargetDesc;
+
+// Debug views of the scene geometry, P cycles through them
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PolygonView {
+    #[default]
+    Shaded,
+    Wireframe,
+    WireframeOverShaded,
+    Points,
+}
+
+impl PolygonView {
+    pub fn next(self) -> Self {
+        match self {
+            PolygonView::Shaded => PolygonView::Wireframe,
+            PolygonView::Wireframe => PolygonView::WireframeOverShaded,
+            PolygonView::WireframeOverShaded => PolygonView::Points,
+            PolygonView::Points => PolygonView::Shaded,
+        }
+    }
+}
+
+// Line polygon mode isn't available everywhere, e.g. not on WebGL. There
+// wireframes are triangles that only keep the pixels near their edges, which
+// needs a mesh copy without shared vertices, see Mesh::barycentric_vertex_buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WireframeMethod {
+    PolygonMode,
+    Barycentric,
+}
+
+impl WireframeMethod {
+    // Optional features the debug views use when the adapter has them
+    pub const FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE.union(wgpu::Features::POLYGON_MODE_POINT);
+
+    pub fn for_features(features: wgpu::Features) -> Self {
+        if features.contains(wgpu::Features::POLYGON_MODE_LINE) {
+            WireframeMethod::PolygonMode
+        } else {
+            WireframeMethod::Barycentric
+        }
+    }
+}
+
+// Collects the state of a render pipeline. The defaults are what most passes
+// here use: vs_main and fs_main of one shader, triangle lists with back faces
+// culled, REPLACE blending and Less depth test with writes, counter clockwise
+// front faces and a single sample.
+pub struct RenderPipelineBuilder<'a> {
+    label: Option<&'a str>,
+    layout: Option<&'a Arc<wgpu::PipelineLayout>>,
+    shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    vertex_entry_point: &'a str,
+    fragment_entry_point: &'a str,
+    vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    // Without a color format there is no fragment stage, e.g. for depth only passes
+    color_format: Option<wgpu::TextureFormat>,
+    blend: Option<wgpu::BlendState>,
+    topology: wgpu::PrimitiveTopology,
+    front_face: wgpu::FrontFace,
+    cull_mode: Option<wgpu::Face>,
+    polygon_mode: wgpu::PolygonMode,
     depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(&shader);
-
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: vertex_layouts,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            }],
-        }),
-        primitive: wgpu::PrimitiveState {
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    depth_bias: wgpu::DepthBiasState,
+    sample_count: u32,
+}
+
+impl<'a> Default for RenderPipelineBuilder<'a> {
+    fn default() -> Self {
+        Self {
+            label: Some("Render Pipeline"),
+            layout: None,
+            shader: None,
+            vertex_entry_point: "vs_main",
+            fragment_entry_point: "fs_main",
+            vertex_layouts: &[],
+            color_format: None,
+            blend: Some(wgpu::BlendState::REPLACE),
             topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
             polygon_mode: wgpu::PolygonMode::Fill,
-            // Requires Features::DEPTH_CLIP_CONTROL
-            unclipped_depth: false,
-            // Requires Features::CONSERVATIVE_RASTERIZATION
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
+            depth_format: None,
             depth_write_enabled: true,
             depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: Option::None,
-    })
+            depth_bias: wgpu::DepthBiasState::default(),
+            sample_count: 1,
+        }
+    }
 }
 
-pub fn create_render_pipeline_separate_shaders(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    vertex_shader: wgpu::ShaderModuleDescriptor,
-    fragment_shader: wgpu::ShaderModuleDescriptor,
-) -> wgpu::RenderPipeline {
-    let vertex_shader = device.create_shader_module(&vertex_shader);
-    let fragment_shader = device.create_shader_module(&fragment_shader);
-
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &vertex_shader,
-            entry_point: "main",
-            buffers: vertex_layouts,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &fragment_shader,
-            entry_point: "main",
-            targets: &[wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
+impl<'a> RenderPipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    // Without a layout wgpu derives one from the shader. It's shared so the
+    // pipeline cache can keep it alive.
+    pub fn layout(mut self, layout: &'a Arc<wgpu::PipelineLayout>) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    // One module holding both stages
+    pub fn shader(mut self, shader: wgpu::ShaderModuleDescriptor<'a>) -> Self {
+        self.shader = Some(shader);
+        self
+    }
+
+    pub fn vertex_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry_point = entry_point;
+        self
+    }
+
+    pub fn fragment_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.fragment_entry_point = entry_point;
+        self
+    }
+
+    pub fn vertex_layouts(mut self, vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.vertex_layouts = vertex_layouts;
+        self
+    }
+
+    pub fn color_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.color_format = Some(format);
+        self
+    }
+
+    // None writes the fragment color as is
+    pub fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    // Line needs Features::POLYGON_MODE_LINE, Point needs Features::POLYGON_MODE_POINT
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    // None leaves out the depth stencil state
+    pub fn depth_format(mut self, format: Option<wgpu::TextureFormat>) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write_enabled = enabled;
+        self
+    }
+
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = compare;
+        self
+    }
+
+    pub fn depth_bias(mut self, bias: wgpu::DepthBiasState) -> Self {
+        self.depth_bias = bias;
+        self
+    }
+
+    // Has to match the sample count of the attachments drawn into
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.sample_count = count;
+        self
+    }
+
+    // Color and depth format and sample count of a render target
+    pub fn render_target(self, target: &RenderTargetDesc) -> Self {
+        self.color_format(target.color_format)
+            .depth_format(target.depth_format)
+            .sample_count(target.sample_count)
+    }
+
+    pub fn pipeline_layout(&self) -> Option<Arc<wgpu::PipelineLayout>> {
+        self.layout.cloned()
+    }
+
+    // Raster state and wireframe.wgsl entry points for a debug view, on top of
+    // what the shaded pipeline uses. The overlay doesn't write depth and is
+    // pulled towards the camera so it wins against the shaded surface below.
+    pub fn polygon_view(self, view: PolygonView, features: wgpu::Features) -> Self {
+        match view {
+            PolygonView::Shaded => self,
+            PolygonView::Wireframe => self.wireframe(features).cull_mode(None),
+            PolygonView::WireframeOverShaded => self
+                .wireframe(features)
+                .depth_compare(wgpu::CompareFunction::LessEqual)
+                .depth_write(false)
+                .depth_bias(wgpu::DepthBiasState {
+                    constant: -2,
+                    slope_scale: -1.0,
+                    clamp: 0.0,
                 }),
-                write_mask: wgpu::ColorWrites::ALL,
-            }],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: wgpu::PolygonMode::Fill,
-            // Requires Features::DEPTH_CLIP_CONTROL
-            unclipped_depth: false,
-            // Requires Features::CONSERVATIVE_RASTERIZATION
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            PolygonView::Points => {
+                let points = self.vertex_entry_point("vs_main").fragment_entry_point("fs_line");
+                // Point lists draw every index, back faces included
+                if features.contains(wgpu::Features::POLYGON_MODE_POINT) {
+                    points.polygon_mode(wgpu::PolygonMode::Point)
+                } else {
+                    points.topology(wgpu::PrimitiveTopology::PointList)
+                }
+            }
+        }
+    }
+
+    fn wireframe(self, features: wgpu::Features) -> Self {
+        match WireframeMethod::for_features(features) {
+            WireframeMethod::PolygonMode => self
+                .vertex_entry_point("vs_main")
+                .fragment_entry_point("fs_line")
+                .polygon_mode(wgpu::PolygonMode::Line),
+            WireframeMethod::Barycentric => self
+                .vertex_entry_point("vs_barycentric")
+                .fragment_entry_point("fs_barycentric")
+                .blend(Some(wgpu::BlendState::ALPHA_BLENDING)),
+        }
+    }
+
+    // Labels of the shader modules, the cache invalidates pipelines by them
+    pub fn shader_labels(&self) -> Vec<String> {
+        self.shader
+            .iter()
+            .filter_map(|shader| shader.label)
+            .map(str::to_string)
+            .collect()
+    }
+
+    // Panics without a shader, that is a bug in the caller rather than
+    // something to recover from. Shader and validation errors go to the
+    // device's error handler or the current error scope as usual.
+    pub fn build(self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(
+            self.shader.as_ref().expect("RenderPipelineBuilder needs a shader"),
+        );
+
+        let targets = self.color_format.map(|format| wgpu::ColorTargetState {
             format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: Option::None,
-    })
-}
\ No newline at end of file
+            blend: self.blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: self.label,
+            layout: self.layout.map(|layout| layout.as_ref()),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: self.vertex_entry_point,
+                buffers: self.vertex_layouts,
+            },
+            fragment: targets.as_ref().map(|target| wgpu::FragmentState {
+                module: &shader,
+                entry_point: self.fragment_entry_point,
+                targets: std::slice::from_ref(target),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: self.topology,
+                strip_index_format: None,
+                front_face: self.front_face,
+                cull_mode: self.cull_mode,
+                polygon_mode: self.polygon_mode,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: self.depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: self.depth_write_enabled,
+                depth_compare: self.depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: self.depth_bias,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+}
+
+// Everything but the labels that goes into a pipeline, owned so the cache can
+// compare it against the pipeline it finds. Shaders are compared by their
+// source and layouts by address, the cache holds on to the layouts so an
+// address can't be reused while pipelines built with it are cached.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    layout: Option<usize>,
+    shader: Option<ShaderKey>,
+    vertex_entry_point: String,
+    fragment_entry_point: String,
+    vertex_layouts: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    color_format: Option<wgpu::TextureFormat>,
+    blend: Option<wgpu::BlendState>,
+    topology: wgpu::PrimitiveTopology,
+    front_face: wgpu::FrontFace,
+    cull_mode: Option<wgpu::Face>,
+    polygon_mode: wgpu::PolygonMode,
+    depth_format: Option<wgpu::TextureFormat>,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    // Constant, then slope scale and clamp by their bits
+    depth_bias: (i32, u32, u32),
+    sample_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ShaderKey {
+    Wgsl(String),
+    Glsl {
+        source: String,
+        stage: naga::ShaderStage,
+        // Sorted by name
+        defines: Vec<(String, String)>,
+    },
+    // Other sources depend on optional wgpu features, only the label tells
+    // them apart
+    Other(Option<String>),
+}
+
+impl ShaderKey {
+    fn new(shader: &wgpu::ShaderModuleDescriptor) -> Self {
+        match &shader.source {
+            wgpu::ShaderSource::Wgsl(source) => ShaderKey::Wgsl(source.to_string()),
+            wgpu::ShaderSource::Glsl { shader: source, stage, defines } => {
+                let mut defines = defines.iter().map(|(k, v)| (k.clone(), v.clone())).collect::<Vec<_>>();
+                defines.sort();
+                ShaderKey::Glsl {
+                    source: source.to_string(),
+                    stage: *stage,
+                    defines,
+                }
+            }
+            #[allow(unreachable_patterns)]
+            _ => ShaderKey::Other(shader.label.map(str::to_string)),
+        }
+    }
+}
+
+impl RenderPipelineBuilder<'_> {
+    pub fn key(&self) -> PipelineKey {
+        PipelineKey {
+            layout: self.layout.map(|layout| Arc::as_ptr(layout) as usize),
+            shader: self.shader.as_ref().map(ShaderKey::new),
+            vertex_entry_point: self.vertex_entry_point.to_string(),
+            fragment_entry_point: self.fragment_entry_point.to_string(),
+            vertex_layouts: self.vertex_layouts
+                .iter()
+                .map(|layout| (layout.array_stride, layout.step_mode, layout.attributes.to_vec()))
+                .collect(),
+            color_format: self.color_format,
+            blend: self.blend,
+            topology: self.topology,
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            polygon_mode: self.polygon_mode,
+            depth_format: self.depth_format,
+            depth_write_enabled: self.depth_write_enabled,
+            depth_compare: self.depth_compare,
+            depth_bias: (
+                self.depth_bias.constant,
+                self.depth_bias.slope_scale.to_bits(),
+                self.depth_bias.clamp.to_bits(),
+            ),
+            sample_count: self.sample_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader(source: &str) -> wgpu::ShaderModuleDescriptor<'_> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some("test.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }
+    }
+
+    #[test]
+    fn labels_are_not_part_of_the_key() {
+        let a = RenderPipelineBuilder::new().label("A").shader(shader("a")).color_format(wgpu::TextureFormat::Rgba8Unorm);
+        let b = RenderPipelineBuilder::new().label("B").shader(shader("a")).color_format(wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(a.key(), b.key());
+    }
+
+    #[test]
+    fn state_and_sources_are_part_of_the_key() {
+        let base = || RenderPipelineBuilder::new().shader(shader("a")).color_format(wgpu::TextureFormat::Rgba8Unorm);
+        let key = base().key();
+
+        assert_ne!(key, RenderPipelineBuilder::new().shader(shader("b")).color_format(wgpu::TextureFormat::Rgba8Unorm).key());
+        assert_ne!(key, base().color_format(wgpu::TextureFormat::Rgba8UnormSrgb).key());
+        assert_ne!(key, base().cull_mode(None).key());
+        assert_ne!(key, base().front_face(wgpu::FrontFace::Cw).key());
+        assert_ne!(key, base().sample_count(4).key());
+        assert_ne!(key, base().fragment_entry_point("fs_line").key());
+        assert_ne!(key, base().depth_bias(wgpu::DepthBiasState { constant: 0, slope_scale: -1.0, clamp: 0.0 }).key());
+        assert_ne!(key, base().polygon_view(PolygonView::Points, wgpu::Features::empty()).key());
+    }
+}
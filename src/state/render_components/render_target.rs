@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use super::pipeline_cache::PipelineCache;
+use super::render_pipeline::RenderPipelineBuilder;
+use super::sampler::SamplerCache;
+use super::texture::Texture;
+
+// How a render target picks its resolution
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TargetSize {
+    // Follows the surface, resized from State::resize
+    Surface,
+    // The surface size times a factor, e.g. 2.0 to supersample
+    Scaled(f32),
+    // Never resized
+    Fixed { width: u32, height: u32 },
+}
+
+impl TargetSize {
+    pub fn resolve(&self, surface_width: u32, surface_height: u32) -> (u32, u32) {
+        match *self {
+            TargetSize::Surface => (surface_width, surface_height),
+            TargetSize::Scaled(factor) => (
+                (surface_width as f32 * factor).round() as u32,
+                (surface_height as f32 * factor).round() as u32,
+            ),
+            TargetSize::Fixed { width, height } => (width, height),
+        }
+    }
+}
+
+// MSAA=4 multisamples the scene. Only 1 and 4 samples work with every
+// adapter and format, anything else falls back to 1.
+pub fn sample_count_from_env() -> u32 {
+    match std::env::var("MSAA").map(|count| count.parse::<u32>()) {
+        Ok(Ok(count)) if count == 1 || count == 4 => count,
+        Ok(_) => {
+            log::warn!("MSAA must be 1 or 4, using 1");
+            1
+        }
+        Err(_) => 1,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderTargetDesc {
+    pub label: String,
+    pub color_format: wgpu::TextureFormat,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub sample_count: u32,
+    pub size: TargetSize,
+}
+
+impl RenderTargetDesc {
+    // Surface sized color without depth, one sample
+    pub fn new(label: &str, color_format: wgpu::TextureFormat) -> Self {
+        Self {
+            label: label.to_string(),
+            color_format,
+            depth_format: None,
+            sample_count: 1,
+            size: TargetSize::Surface,
+        }
+    }
+
+    pub fn with_depth(mut self, depth_format: Option<wgpu::TextureFormat>) -> Self {
+        self.depth_format = depth_format;
+        self
+    }
+
+    // Pipelines drawing into the target need the same count
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn with_size(mut self, size: TargetSize) -> Self {
+        self.size = size;
+        self
+    }
+}
+
+// Color and optional depth attachments a pass can render into instead of the
+// swapchain. color is always single sampled so other passes can sample or
+// copy it, with sample_count > 1 the pass draws into msaa_color and resolves.
+pub struct RenderTarget {
+    pub desc: RenderTargetDesc,
+    pub color: Texture,
+    msaa_color: Option<Texture>,
+    pub depth: Option<Texture>,
+    // Created by the first Blit::draw from color, resizing drops it with color
+    blit_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        desc: RenderTargetDesc,
+        surface_width: u32,
+        surface_height: u32,
+        samplers: &mut SamplerCache,
+    ) -> Self {
+        let (width, height) = desc.size.resolve(surface_width, surface_height);
+
+        let color = Texture::create_render_target(
+            device,
+            &desc.label,
+            width,
+            height,
+            desc.color_format,
+            1,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            samplers,
+        );
+        let msaa_color = (desc.sample_count > 1).then(|| {
+            Texture::create_render_target(
+                device,
+                &format!("{} MSAA", desc.label),
+                width,
+                height,
+                desc.color_format,
+                desc.sample_count,
+                wgpu::TextureUsages::empty(),
+                samplers,
+            )
+        });
+        // Multisampled depth is only ever an attachment, the GL backend can't
+        // complete a framebuffer with a sampleable one
+        let depth_usage = if desc.sample_count > 1 {
+            wgpu::TextureUsages::empty()
+        } else {
+            wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        let depth = desc.depth_format.map(|format| {
+            Texture::create_render_target(
+                device,
+                &format!("{} Depth", desc.label),
+                width,
+                height,
+                format,
+                desc.sample_count,
+                depth_usage,
+                samplers,
+            )
+        });
+
+        Self { desc, color, msaa_color, depth, blit_bind_group: None }
+    }
+
+    // Recreates the attachments for a new surface size. Returns false if the
+    // size didn't change, e.g. for fixed targets.
+    pub fn resize(&mut self, device: &wgpu::Device, surface_width: u32, surface_height: u32, samplers: &mut SamplerCache) -> bool {
+        let (width, height) = self.desc.size.resolve(surface_width, surface_height);
+        if (width.max(1), height.max(1)) == self.size() {
+            return false;
+        }
+        *self = Self::new(device, self.desc.clone(), surface_width, surface_height, samplers);
+        true
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.color.size.width, self.color.size.height)
+    }
+
+    pub fn color_attachment(&self, load: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPassColorAttachment<'_> {
+        let ops = wgpu::Operations { load, store: true };
+        match &self.msaa_color {
+            Some(msaa_color) => wgpu::RenderPassColorAttachment {
+                view: &msaa_color.view,
+                resolve_target: Some(&self.color.view),
+                ops,
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: &self.color.view,
+                resolve_target: None,
+                ops,
+            },
+        }
+    }
+
+    pub fn depth_attachment(&self, load: wgpu::LoadOp<f32>) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        self.depth.as_ref().map(|depth| wgpu::RenderPassDepthStencilAttachment {
+            view: &depth.view,
+            depth_ops: Some(wgpu::Operations { load, store: true }),
+            stencil_ops: None,
+        })
+    }
+}
+
+// Draws a texture over a whole color attachment, e.g. to present an offscreen
+// target on the swapchain
+pub struct Blit {
+    pipeline: Arc<wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Blit {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat, pipelines: &mut PipelineCache) -> Self {
+        let builder = RenderPipelineBuilder::new()
+            .label("Blit Pipeline")
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: Some("blit.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shader/wgsl/blit.wgsl").into()),
+            })
+            .color_format(target_format)
+            .blend(None)
+            .cull_mode(None);
+        let pipeline = pipelines.get(device, builder);
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+        Self { pipeline, bind_group_layout }
+    }
+
+    // Draws the color attachment of source. The bind group for it is kept on
+    // source, so a target should only ever be drawn by one Blit.
+    pub fn draw(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, source: &mut RenderTarget, target: &wgpu::TextureView) {
+        let color = &source.color;
+        let bind_group = source.blit_bind_group.get_or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&color.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&color.sampler),
+                    },
+                ],
+                label: Some("blit_bind_group"),
+            })
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_sizes_resolve_against_the_surface() {
+        assert_eq!(TargetSize::Surface.resolve(640, 480), (640, 480));
+        assert_eq!(TargetSize::Scaled(2.0).resolve(640, 480), (1280, 960));
+        assert_eq!(TargetSize::Scaled(0.5).resolve(641, 481), (321, 241));
+        let fixed = TargetSize::Fixed { width: 256, height: 128 };
+        assert_eq!(fixed.resolve(640, 480), (256, 128));
+        assert_eq!(fixed.resolve(1920, 1080), (256, 128));
+    }
+}
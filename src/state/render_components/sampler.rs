@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU8;
+use std::sync::Arc;
+
+// Everything that makes up a sampler. wgpu has no LOD bias on samplers,
+// raising lod_min_clamp is the closest thing to a positive bias.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub lod_min_clamp: f32,
+    pub lod_max_clamp: f32,
+    pub compare: Option<wgpu::CompareFunction>,
+    // 1, 2, 4, 8 or 16, needs linear filtering
+    pub anisotropy_clamp: Option<NonZeroU8>,
+    pub border_color: Option<wgpu::SamplerBorderColor>,
+}
+
+// Trilinear, clamped to the edge
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: f32::MAX,
+            compare: None,
+            anisotropy_clamp: None,
+            border_color: None,
+        }
+    }
+}
+
+impl SamplerDesc {
+    pub fn with_address_mode(self, mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode_u: mode,
+            address_mode_v: mode,
+            address_mode_w: mode,
+            ..self
+        }
+    }
+
+    // Anisotropic filtering is dropped unless every filter is linear, wgpu
+    // doesn't allow it otherwise
+    pub fn descriptor<'a>(&self, label: Option<&'a str>) -> wgpu::SamplerDescriptor<'a> {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|&filter| filter == wgpu::FilterMode::Linear);
+        if self.anisotropy_clamp.is_some() && !linear {
+            log::warn!("Anisotropic filtering needs linear filters, ignoring it for {:?}", label);
+        }
+
+        wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            lod_min_clamp: self.lod_min_clamp,
+            lod_max_clamp: self.lod_max_clamp,
+            compare: self.compare,
+            anisotropy_clamp: self.anisotropy_clamp.filter(|_| linear),
+            border_color: self.border_color,
+        }
+    }
+}
+
+// The clamps are compared bitwise so descriptions can be used as keys
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address_mode_u.hash(state);
+        self.address_mode_v.hash(state);
+        self.address_mode_w.hash(state);
+        self.mag_filter.hash(state);
+        self.min_filter.hash(state);
+        self.mipmap_filter.hash(state);
+        self.lod_min_clamp.to_bits().hash(state);
+        self.lod_max_clamp.to_bits().hash(state);
+        self.compare.hash(state);
+        self.anisotropy_clamp.hash(state);
+        self.border_color.hash(state);
+    }
+}
+
+// Hands out one shared sampler per distinct description
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self {
+            samplers: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, desc: &SamplerDesc) -> Arc<wgpu::Sampler> {
+        self.samplers
+            .entry(*desc)
+            .or_insert_with(|| Arc::new(device.create_sampler(&desc.descriptor(Some("Cached Sampler")))))
+            .clone()
+    }
+}
+
+// Splits a texture statement from an MTL file, like "-clamp on -s 2 2 wood.png",
+// into the file name and the sampler its options ask for. MTL textures repeat
+// unless "-clamp on" is given. Options that don't affect sampling are skipped.
+pub fn parse_mtl_texture(spec: &str) -> (String, SamplerDesc) {
+    let mut desc = SamplerDesc::default().with_address_mode(wgpu::AddressMode::Repeat);
+    let tokens = spec.split_whitespace().collect::<Vec<_>>();
+
+    let mut i = 0;
+    while i < tokens.len() && tokens[i].starts_with('-') && tokens[i].len() > 1 {
+        let option = tokens[i];
+        i += 1;
+        match option {
+            "-clamp" => {
+                let mode = match tokens.get(i) {
+                    Some(&"on") => wgpu::AddressMode::ClampToEdge,
+                    _ => wgpu::AddressMode::Repeat,
+                };
+                desc = desc.with_address_mode(mode);
+                i += 1;
+            }
+            "-blendu" | "-blendv" | "-cc" | "-boost" | "-texres" | "-bm" | "-imfchan" | "-type" => i += 1,
+            "-mm" => i += 2,
+            // Up to three numbers
+            "-o" | "-s" | "-t" => {
+                let end = (i + 3).min(tokens.len());
+                while i < end && tokens[i].parse::<f32>().is_ok() {
+                    i += 1;
+                }
+            }
+            _ => log::warn!("Unknown MTL texture option {} in {:?}", option, spec),
+        }
+    }
+
+    (tokens[i.min(tokens.len())..].join(" "), desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anisotropy_needs_linear_filters() {
+        let anisotropic = SamplerDesc {
+            anisotropy_clamp: NonZeroU8::new(16),
+            ..SamplerDesc::default()
+        };
+        assert_eq!(anisotropic.descriptor(None).anisotropy_clamp, NonZeroU8::new(16));
+
+        let nearest_mips = SamplerDesc {
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..anisotropic
+        };
+        assert_eq!(nearest_mips.descriptor(None).anisotropy_clamp, None);
+    }
+
+    fn repeat() -> SamplerDesc {
+        SamplerDesc::default().with_address_mode(wgpu::AddressMode::Repeat)
+    }
+
+    #[test]
+    fn plain_file_name_repeats() {
+        assert_eq!(parse_mtl_texture("wood.png"), ("wood.png".to_string(), repeat()));
+        assert_eq!(parse_mtl_texture("  wood.png "), ("wood.png".to_string(), repeat()));
+    }
+
+    #[test]
+    fn clamp_option_picks_the_address_mode() {
+        let clamped = SamplerDesc::default().with_address_mode(wgpu::AddressMode::ClampToEdge);
+        assert_eq!(parse_mtl_texture("-clamp on wood.png"), ("wood.png".to_string(), clamped));
+        assert_eq!(parse_mtl_texture("-clamp off wood.png"), ("wood.png".to_string(), repeat()));
+    }
+
+    #[test]
+    fn other_options_and_their_arguments_are_skipped() {
+        assert_eq!(
+            parse_mtl_texture("-blendu off -mm 0.1 0.9 -s 2 2 -o 0.5 -bm 1 wood.png"),
+            ("wood.png".to_string(), repeat()),
+        );
+        // -s takes up to three numbers, what follows them is the file name
+        assert_eq!(parse_mtl_texture("-s 1 2 3 4.png"), ("4.png".to_string(), repeat()));
+        assert_eq!(parse_mtl_texture("-s 2 wood.png"), ("wood.png".to_string(), repeat()));
+    }
+
+    #[test]
+    fn file_names_keep_their_spaces() {
+        assert_eq!(
+            parse_mtl_texture("-clamp on old wood.png"),
+            ("old wood.png".to_string(), SamplerDesc::default()),
+        );
+    }
+
+    #[test]
+    fn options_without_a_file_name_give_an_empty_name() {
+        assert_eq!(parse_mtl_texture("-clamp"), (String::new(), repeat()));
+        assert_eq!(parse_mtl_texture(""), (String::new(), repeat()));
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::*;
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use super::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use super::cubemap;
+use super::pipeline_cache::PipelineCache;
+use super::render_pipeline::RenderPipelineBuilder;
+use super::render_target::RenderTargetDesc;
+use super::sampler::{SamplerCache, SamplerDesc};
+use super::texture::Texture;
+
+// Shader modules are labeled with their file, hot reload finds them by it
+pub const SHADER_FILE: &str = "skybox.wgsl";
+
+// What fills the pixels no geometry covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Background {
+    #[default]
+    ClearColor,
+    Skybox,
+}
+
+// SKYBOX=sky.hdr loads a panorama, six comma separated files load the faces
+// in +X, -X, +Y, -Y, +Z, -Z order. Paths are relative to the asset directories.
+pub fn files_from_env() -> Option<Vec<PathBuf>> {
+    let value = std::env::var("SKYBOX").ok()?;
+    let files = value
+        .split(',')
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    (!files.is_empty()).then_some(files)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl SkyUniform {
+    // Only the camera rotation matters, the sky is infinitely far away
+    fn new(camera: &Camera) -> Self {
+        let mut view = cgmath::Matrix4::look_at_rh(camera.eye, camera.target, camera.up);
+        view.w = cgmath::Vector4::unit_w();
+        let proj = cgmath::perspective(cgmath::Deg(camera.fovy), camera.aspect, camera.znear, camera.zfar);
+        let view_proj = OPENGL_TO_WGPU_MATRIX * proj * view;
+        Self {
+            inv_view_proj: view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity).into(),
+        }
+    }
+}
+
+// Draws a cubemap on the far plane, meant to run after the opaque geometry
+// inside the color pass so only uncovered pixels are shaded
+pub struct Skybox {
+    texture: Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: Arc<wgpu::PipelineLayout>,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    target: RenderTargetDesc,
+}
+
+impl Skybox {
+    // Starts out with a generated gradient until set_texture is called
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &RenderTargetDesc,
+        camera: &Camera,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+    ) -> Result<Self> {
+        let faces = cubemap::gradient_faces(64, [0.15, 0.35, 0.8], [0.7, 0.8, 0.9], [0.2, 0.18, 0.15]);
+        let texture = Texture::from_cube_faces(device, queue, &faces, Some("Gradient Sky"), samplers, SamplerDesc::default())?;
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniform::new(camera)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &texture);
+
+        let pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        }));
+        let pipeline = Self::create_pipeline(
+            device,
+            pipelines,
+            &pipeline_layout,
+            target,
+            include_str!("../shader/wgsl/skybox.wgsl"),
+        );
+
+        Ok(Self {
+            texture,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+            target: target.clone(),
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    // Draws at depth 1.0 without writing depth, so it only passes where the
+    // depth buffer still holds its clear value
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        layout: &Arc<wgpu::PipelineLayout>,
+        target: &RenderTargetDesc,
+        source: &str,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let builder = RenderPipelineBuilder::new()
+            .label("Skybox Pipeline")
+            .layout(layout)
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: Some(SHADER_FILE),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            })
+            .render_target(target)
+            // The fullscreen triangle winds clockwise
+            .front_face(wgpu::FrontFace::Cw)
+            .depth_write(false)
+            .depth_compare(wgpu::CompareFunction::LessEqual);
+        pipelines.get(device, builder)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_shader(&mut self, device: &wgpu::Device, pipelines: &mut PipelineCache, source: &str) -> Result<()> {
+        pipelines.invalidate_shader(SHADER_FILE);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = Self::create_pipeline(device, pipelines, &self.pipeline_layout, &self.target, source);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            pipelines.invalidate_shader(SHADER_FILE);
+            bail!("{}", error);
+        }
+        self.pipeline = pipeline;
+        Ok(())
+    }
+
+    // Replaces the sky with a cube texture, e.g. from Texture::from_cube_faces
+    pub fn set_texture(&mut self, device: &wgpu::Device, texture: Texture) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &texture);
+        self.texture = texture;
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[SkyUniform::new(camera)]));
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1,118 +1,559 @@
 use image::GenericImageView;
 use anyhow::*;
 
+use std::sync::Arc;
+
+use super::compressed::{self, CompressedImage};
+use super::cubemap;
+use super::mipmaps::{self, MipmapMode};
+use super::pipeline_cache::PipelineCache;
+use super::sampler::{SamplerCache, SamplerDesc};
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    pub mip_level_count: u32,
+    pub sampler: Arc<wgpu::Sampler>,
+    pub sampler_desc: SamplerDesc,
+}
+
+// Like image::load_from_memory, but Radiance files are decoded to floats
+// instead of being tone mapped to 8 bit
+pub fn load_image(bytes: &[u8]) -> Result<image::DynamicImage> {
+    if bytes.starts_with(b"#?") {
+        let decoder = image::codecs::hdr::HdrDecoder::new(bytes)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let img = image::Rgb32FImage::from_fn(metadata.width, metadata.height, |x, y| {
+            pixels[(y * metadata.width + x) as usize]
+        });
+        return Ok(image::DynamicImage::ImageRgb32F(img));
+    }
+    Ok(image::load_from_memory(bytes)?)
+}
+
+// Contents of a texture file, decoded far enough to be uploaded
+pub enum TextureData {
+    Image(image::DynamicImage),
+    Compressed(CompressedImage),
+}
+
+pub fn load_texture_data(bytes: &[u8]) -> Result<TextureData> {
+    if compressed::is_container(bytes) {
+        Ok(TextureData::Compressed(compressed::parse(bytes)?))
+    } else {
+        Ok(TextureData::Image(load_image(bytes)?))
+    }
+}
+
+// The GL backend decides the texture target from the layer count. One layer
+// becomes a plain 2D texture and a multiple of six square layers a cube map,
+// so arrays get padded with unused layers until they are neither.
+pub fn array_layer_count(layers: u32, square: bool) -> u32 {
+    let mut count = layers.max(2);
+    while square && count % 6 == 0 {
+        count += 1;
+    }
+    count
 }
 
 impl Texture {
-    pub fn from_bytes(
+    pub fn from_data(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8], 
-        label: &str
+        data: &TextureData,
+        label: Option<&str>,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+        sampler_desc: SamplerDesc,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        match data {
+            TextureData::Image(img) => Self::from_image(device, queue, img, label, samplers, pipelines, sampler_desc),
+            TextureData::Compressed(img) => Self::from_compressed(device, queue, img, label, samplers, pipelines, sampler_desc),
+        }
+    }
+
+    // Uploads the blocks as they are if the device supports the format,
+    // otherwise decodes them on the CPU into an 8 bit texture. Block
+    // compressed levels can't be rendered to, so files with a single level are
+    // uploaded without mipmaps rather than giving up the compression.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &CompressedImage,
+        label: Option<&str>,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+        sampler_desc: SamplerDesc,
+    ) -> Result<Self> {
+        let info = img.format.describe();
+        let (block_width, block_height) = (info.block_dimensions.0 as u32, info.block_dimensions.1 as u32);
+        // Compressed textures have to be a whole number of blocks in size
+        let aligned = img.width % block_width == 0 && img.height % block_height == 0;
+        let supported = device.features().contains(info.required_features) && aligned;
+
+        if !supported {
+            log::info!("{:?} can't be used for {:?}, decoding it on the CPU", img.format, label);
+            let format = if info.srgb {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            } else {
+                wgpu::TextureFormat::Rgba8Unorm
+            };
+            let rgba = image::DynamicImage::ImageRgba8(compressed::decode(img)?);
+            return Self::from_image_with_format(device, queue, &rgba, label, samplers, pipelines, sampler_desc, format);
+        }
+
+        let size = wgpu::Extent3d {
+            width: img.width,
+            height: img.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: img.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: img.format,
+                // COPY_SRC so it can be packed into an array later
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            }
+        );
+
+        for (mip_level, data) in img.levels.iter().enumerate() {
+            let (blocks_x, blocks_y, _) = compressed::level_size(img.format, img.width, img.height, mip_level as u32);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(blocks_x * info.block_size as u32),
+                    rows_per_image: std::num::NonZeroU32::new(blocks_y),
+                },
+                // Levels smaller than a block still cover a whole one
+                wgpu::Extent3d {
+                    width: blocks_x * block_width,
+                    height: blocks_y * block_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = samplers.get(device, &sampler_desc);
+        let (format, mip_level_count) = (img.format, img.levels.len() as u32);
+
+        Ok(Self { texture, view, size, format, mip_level_count, sampler, sampler_desc })
+    }
+
+    // 1x1 texture, for generated models that don't come with an image
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+    ) -> Result<Self> {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image(device, queue, &img, Some(label), samplers, pipelines, SamplerDesc::default())
+    }
+
+    // Float images (.hdr, .exr) keep their range as half floats, everything
+    // else is treated as 8 bit sRGB color. Every texture here is filtered,
+    // which Rgba32Float doesn't support.
+    pub fn format_for_image(img: &image::DynamicImage) -> wgpu::TextureFormat {
+        match img {
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => {
+                wgpu::TextureFormat::Rgba16Float
+            }
+            _ => wgpu::TextureFormat::Rgba8UnormSrgb,
+        }
     }
 
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
-        label: Option<&str>
+        label: Option<&str>,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+        sampler_desc: SamplerDesc,
+    ) -> Result<Self> {
+        let format = Self::format_for_image(img);
+        Self::from_image_with_format(device, queue, img, label, samplers, pipelines, sampler_desc, format)
+    }
+
+    // Supports Rgba8UnormSrgb, Rgba8Unorm, Rgba16Float and Rgba32Float.
+    // Rgba32Float can't be filtered, so its mips are built on the CPU and it
+    // needs a non filtering sampler and bind group layout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        samplers: &mut SamplerCache,
+        pipelines: &mut PipelineCache,
+        sampler_desc: SamplerDesc,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
-        //let rgba = img.as_rgba8().unwrap();
         let dimensions = img.dimensions();
-        let rgba = img.to_rgba8();
+        let mip_level_count = mipmaps::mip_level_count(dimensions.0, dimensions.1);
+        let mipmap_mode = match format {
+            wgpu::TextureFormat::Rgba32Float => MipmapMode::Cpu,
+            _ => MipmapMode::from_env().unwrap_or(MipmapMode::Gpu),
+        };
+
+        let levels = Self::image_levels(img, format, mipmap_mode)?;
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC;
+        if mipmap_mode == MipmapMode::Gpu {
+            // The GPU mipmap generation renders into the lower levels
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+            }
+        );
+
+        let bytes_per_pixel = format.describe().block_size as u32;
+        for (mip_level, (width, height, data)) in levels.iter().enumerate() {
+            Self::write_mip_level(queue, &texture, mip_level as u32, 0, *width, *height, bytes_per_pixel, data);
+        }
+        if mipmap_mode == MipmapMode::Gpu {
+            mipmaps::generate_mipmaps(device, queue, pipelines, &texture, format, mip_level_count);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = samplers.get(device, &sampler_desc);
+        
+        Ok(Self { texture, view, size, format, mip_level_count, sampler, sampler_desc })
+    }
+
+    // Raw data of level 0, plus the rest of the chain when built on the CPU
+    fn image_levels(
+        img: &image::DynamicImage,
+        format: wgpu::TextureFormat,
+        mipmap_mode: MipmapMode,
+    ) -> Result<Vec<(u32, u32, Vec<u8>)>> {
+        let levels = match format {
+            wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => {
+                let mut levels = vec![img.to_rgba8()];
+                if mipmap_mode == MipmapMode::Cpu {
+                    let srgb = format == wgpu::TextureFormat::Rgba8UnormSrgb;
+                    levels.extend(mipmaps::generate_mipmaps_cpu(&levels[0], srgb));
+                }
+                levels.into_iter().map(|level| (level.width(), level.height(), level.into_raw())).collect()
+            }
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float => {
+                let mut levels = vec![img.to_rgba32f()];
+                if mipmap_mode == MipmapMode::Cpu {
+                    levels.extend(mipmaps::generate_mipmaps_cpu_f32(&levels[0]));
+                }
+                levels
+                    .into_iter()
+                    .map(|level| {
+                        let data = if format == wgpu::TextureFormat::Rgba16Float {
+                            let half = level.as_raw().iter().map(|&v| half::f16::from_f32(v).to_bits()).collect::<Vec<_>>();
+                            bytemuck::cast_slice(&half).to_vec()
+                        } else {
+                            bytemuck::cast_slice(level.as_raw()).to_vec()
+                        };
+                        (level.width(), level.height(), data)
+                    })
+                    .collect()
+            }
+            _ => bail!("Can't create a texture with format {:?} from an image", format),
+        };
+        Ok(levels)
+    }
+
+    // Cube texture from six faces in +X, -X, +Y, -Y, +Z, -Z order. The mip
+    // chain is always built on the CPU, the blit pass only handles 2D views.
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage],
+        label: Option<&str>,
+        samplers: &mut SamplerCache,
+        sampler_desc: SamplerDesc,
+    ) -> Result<Self> {
+        let face_size = cubemap::check_faces(faces)?;
+        let format = Self::format_for_image(&faces[0]);
+        let mip_level_count = mipmaps::mip_level_count(face_size, face_size);
+        let size = wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: cubemap::FACE_COUNT as u32,
+        };
+
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             }
         );
 
+        let bytes_per_pixel = format.describe().block_size as u32;
+        for (layer, face) in faces.iter().enumerate() {
+            let levels = Self::image_levels(face, format, MipmapMode::Cpu)?;
+            for (mip_level, (width, height, data)) in levels.iter().enumerate() {
+                Self::write_mip_level(queue, &texture, mip_level as u32, layer as u32, *width, *height, bytes_per_pixel, data);
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = samplers.get(device, &sampler_desc);
+
+        Ok(Self { texture, view, size, format, mip_level_count, sampler, sampler_desc })
+    }
+
+    // Copies 2D textures of the same size, format and mip count into the
+    // layers of one array texture, sampled with the first one's sampler.
+    // Unused layers are added as needed, see array_layer_count.
+    pub fn pack_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[&Texture],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let first = match layers.first() {
+            Some(first) => *first,
+            None => bail!("Can't pack an empty texture array"),
+        };
+        let mismatch = layers.iter().find(|layer| {
+            layer.size != first.size || layer.format != first.format || layer.mip_level_count != first.mip_level_count
+        });
+        if let Some(layer) = mismatch {
+            bail!(
+                "Array layers have to match, {:?} {:?} with {} mips differs from {:?} {:?} with {} mips",
+                layer.size, layer.format, layer.mip_level_count,
+                first.size, first.format, first.mip_level_count,
+            );
+        }
+
+        let square = first.size.width == first.size.height;
+        let size = wgpu::Extent3d {
+            width: first.size.width,
+            height: first.size.height,
+            depth_or_array_layers: array_layer_count(layers.len() as u32, square),
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: first.mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: first.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            }
+        );
+
+        let info = first.format.describe();
+        let (block_width, block_height) = (info.block_dimensions.0 as u32, info.block_dimensions.1 as u32);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Array Encoder"),
+        });
+        for (layer, source) in layers.iter().enumerate() {
+            for mip_level in 0..first.mip_level_count {
+                // Compressed levels are copied in whole blocks
+                let width = (first.size.width >> mip_level).max(1);
+                let height = (first.size.height >> mip_level).max(1);
+                encoder.copy_texture_to_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &source.texture,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                    },
+                    wgpu::Extent3d {
+                        width: width.div_ceil(block_width) * block_width,
+                        height: height.div_ceil(block_height) * block_height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            size,
+            format: first.format,
+            mip_level_count: first.mip_level_count,
+            sampler: first.sampler.clone(),
+            sampler_desc: first.sampler_desc,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_mip_level(
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        mip_level: u32,
+        layer: u32,
+        width: u32,
+        height: u32,
+        bytes_per_pixel: u32,
+        data: &[u8],
+    ) {
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
             },
-            &rgba,
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
+                bytes_per_row: std::num::NonZeroU32::new(bytes_per_pixel * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
             },
-            size,
-        );
-
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
         );
-        
-        Ok(Self { texture, view, sampler })
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-    
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+
+    // Single level texture that passes render into. Multisampled ones can
+    // only be resolved, not sampled with the filtering sampler it gets.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        usage: wgpu::TextureUsages,
+        samplers: &mut SamplerCache,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
-        let desc = wgpu::TextureDescriptor {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-        };
-        let texture = device.create_texture(&desc);
+            format,
+            usage: usage | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Linear,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                compare: None,
-                lod_min_clamp: -100.0,
-                lod_max_clamp: 100.0,
-                ..Default::default()
+        let sampler_desc = SamplerDesc::default();
+        let sampler = samplers.get(device, &sampler_desc);
+
+        Self { texture, view, size, format, mip_level_count: 1, sampler, sampler_desc }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radiance_file(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [f32; 3]) -> Vec<u8> {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| image::Rgb(pixel(x, y)))
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        image::codecs::hdr::HdrEncoder::new(&mut bytes)
+            .encode(&pixels, width as usize, height as usize)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn radiance_files_decode_to_floats_above_one() {
+        let bytes = radiance_file(3, 2, |x, y| [x as f32 * 4.0, 0.5, y as f32 + 0.25]);
+
+        let img = load_image(&bytes).unwrap();
+        let img = match img {
+            image::DynamicImage::ImageRgb32F(img) => img,
+            other => panic!("Expected an Rgb32F image, got {:?}", other.color()),
+        };
+        assert_eq!(img.dimensions(), (3, 2));
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let expected = [x as f32 * 4.0, 0.5, y as f32 + 0.25];
+            for c in 0..3 {
+                // RGBE shares one exponent per pixel, small channels lose some bits
+                assert!((pixel[c] - expected[c]).abs() <= expected[c].max(1.0) / 64.0, "{:?} at {},{}", pixel, x, y);
             }
-        );
+        }
+        assert!(img.get_pixel(2, 0)[0] > 7.0);
+    }
+
+    #[test]
+    fn array_layer_counts_avoid_gl_texture_targets() {
+        assert_eq!(array_layer_count(1, true), 2);
+        assert_eq!(array_layer_count(2, true), 2);
+        assert_eq!(array_layer_count(5, true), 5);
+        assert_eq!(array_layer_count(6, true), 7);
+        assert_eq!(array_layer_count(12, true), 13);
+        // Only square layers can form a cube map
+        assert_eq!(array_layer_count(6, false), 6);
+        assert_eq!(array_layer_count(1, false), 2);
+    }
+
+    #[test]
+    fn float_images_pick_a_float_format() {
+        let hdr = load_image(&radiance_file(1, 1, |_, _| [2.0, 2.0, 2.0])).unwrap();
+        assert_eq!(Texture::format_for_image(&hdr), wgpu::TextureFormat::Rgba16Float);
 
-        Self { texture, view, sampler }
+        let ldr = image::DynamicImage::ImageRgba8(image::RgbaImage::new(1, 1));
+        assert_eq!(Texture::format_for_image(&ldr), wgpu::TextureFormat::Rgba8UnormSrgb);
     }
 }
@@ -0,0 +1,183 @@
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
+
+use super::render_components::mipmaps::linear_to_srgb;
+use super::render_components::render_target::RenderTarget;
+use super::render_components::texture::Texture;
+
+// Where screenshots are written and at which resolution. SCREENSHOT_DIR,
+// SCREENSHOT_PREFIX and SCREENSHOT_SCALE override the defaults, a scale of 2.0
+// renders the scene at twice the window resolution.
+#[derive(Clone, Debug)]
+pub struct ScreenshotConfig {
+    pub directory: PathBuf,
+    pub prefix: String,
+    pub scale: f32,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("screenshots"),
+            prefix: "screenshot".to_string(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl ScreenshotConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(directory) = std::env::var("SCREENSHOT_DIR") {
+            config.directory = PathBuf::from(directory);
+        }
+        if let Ok(prefix) = std::env::var("SCREENSHOT_PREFIX") {
+            config.prefix = prefix;
+        }
+        match std::env::var("SCREENSHOT_SCALE").map(|scale| scale.parse::<f32>()) {
+            Ok(Ok(scale)) if scale > 0.0 => config.scale = scale,
+            Ok(_) => log::warn!("SCREENSHOT_SCALE must be a positive number, using {}", config.scale),
+            Err(_) => {}
+        }
+        config
+    }
+
+    // e.g. screenshots/screenshot-20240131-235959-042.png, in UTC
+    pub fn next_path(&self) -> PathBuf {
+        self.directory.join(format!("{}-{}.png", self.prefix, timestamp(SystemTime::now())))
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+// Days since 1970-01-01 to a proleptic gregorian date, from
+// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Copies the first mip level of a 2D texture back to the CPU. The texture
+// needs COPY_SRC usage, render targets and loaded textures have it but
+// swapchain frames don't.
+//
+// 8 bit formats are stored as they are, for sRGB formats that already is the
+// encoding PNG expects and linear formats are shown unconverted on screen as
+// well. Float formats hold linear values and are encoded to sRGB.
+pub async fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &Texture) -> Result<RgbaImage> {
+    let width = texture.size.width;
+    let height = texture.size.height;
+    let bytes_per_pixel = match texture.format {
+        wgpu::TextureFormat::Rgba8Unorm
+        | wgpu::TextureFormat::Rgba8UnormSrgb
+        | wgpu::TextureFormat::Bgra8Unorm
+        | wgpu::TextureFormat::Bgra8UnormSrgb => 4,
+        wgpu::TextureFormat::Rgba16Float => 8,
+        format => bail!("Can't read back {:?} textures", format),
+    };
+
+    // Rows in the buffer have to start at multiples of 256 bytes
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    mapping.await?;
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize).take(height as usize) {
+            let row = &row[..unpadded_bytes_per_row as usize];
+            match texture.format {
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                    for pixel in row.chunks_exact(4) {
+                        pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    }
+                }
+                wgpu::TextureFormat::Rgba16Float => {
+                    for (index, bytes) in row.chunks_exact(2).enumerate() {
+                        let value = half::f16::from_le_bytes([bytes[0], bytes[1]]).to_f32();
+                        let encoded = if index % 4 == 3 { value } else { linear_to_srgb(value) };
+                        pixels.push((encoded.clamp(0.0, 1.0) * 255.0).round() as u8);
+                    }
+                }
+                _ => pixels.extend_from_slice(row),
+            }
+        }
+    }
+    buffer.unmap();
+
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("Readback size mismatch"))
+}
+
+pub async fn read_target(device: &wgpu::Device, queue: &wgpu::Queue, target: &RenderTarget) -> Result<RgbaImage> {
+    read_texture(device, queue, &target.color).await
+}
+
+// Writes the image, creating the directory if needed
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_png(image: &RgbaImage, path: &std::path::Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image.save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+// Precomputes the image based lighting maps from an environment cubemap.
+// Every draw renders one face of one mip level of the target cubemap.
+struct Params {
+    face: u32;
+    roughness: f32;
+    // Size of one environment face at level 0
+    source_size: f32;
+    // Level of the environment the irradiance is integrated from
+    source_lod: f32;
+};
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var t_environment: texture_cube<f32>;
+[[group(0), binding(2)]]
+var s_environment: sampler;
+
+let PI: f32 = 3.14159265359;
+let PREFILTER_SAMPLE_COUNT: u32 = 256u;
+let BRDF_SAMPLE_COUNT: u32 = 512u;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+// Same face layout as cubemap::face_direction
+fn face_direction(face: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let u = tex_coords.x * 2.0 - 1.0;
+    let v = tex_coords.y * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch (i32(face)) {
+        case 0: { direction = vec3<f32>(1.0, -v, -u); }
+        case 1: { direction = vec3<f32>(-1.0, -v, u); }
+        case 2: { direction = vec3<f32>(u, 1.0, v); }
+        case 3: { direction = vec3<f32>(u, -1.0, -v); }
+        case 4: { direction = vec3<f32>(u, -v, 1.0); }
+        default: { direction = vec3<f32>(-u, -v, -1.0); }
+    }
+    return normalize(direction);
+}
+
+// Orthonormal basis around n
+fn tangent_to_world(tangent: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, n));
+    let bitangent = cross(n, right);
+    return tangent.x * right + tangent.y * bitangent + tangent.z * n;
+}
+
+fn radical_inverse(index: u32) -> f32 {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(index) / f32(count), radical_inverse(index));
+}
+
+// Half vector around n distributed like the GGX lobe of the given roughness
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return normalize(tangent_to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n));
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Cosine weighted integral of the environment over the hemisphere around the texel direction
+[[stage(fragment)]]
+fn fs_irradiance(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let n = face_direction(params.face, in.tex_coords);
+    let phi_steps = 128;
+    let theta_steps = 32;
+
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0; i < phi_steps; i = i + 1) {
+        let phi = 2.0 * PI * (f32(i) + 0.5) / f32(phi_steps);
+        for (var j = 0; j < theta_steps; j = j + 1) {
+            let theta = 0.5 * PI * (f32(j) + 0.5) / f32(theta_steps);
+            let tangent = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let color = textureSampleLevel(t_environment, s_environment, tangent_to_world(tangent, n), params.source_lod).rgb;
+            irradiance = irradiance + color * cos(theta) * sin(theta);
+        }
+    }
+    irradiance = PI * irradiance / f32(phi_steps * theta_steps);
+    return vec4<f32>(irradiance, 1.0);
+}
+
+// Environment convolved with the GGX lobe of params.roughness, assuming the
+// view direction equals the normal
+[[stage(fragment)]]
+fn fs_prefilter(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let n = face_direction(params.face, in.tex_coords);
+    if (params.roughness <= 0.0) {
+        return vec4<f32>(textureSampleLevel(t_environment, s_environment, n, 0.0).rgb, 1.0);
+    }
+
+    // Solid angle of one environment texel, to pick the level matching each sample
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLE_COUNT; i = i + 1u) {
+        let h = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLE_COUNT), n, params.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(PREFILTER_SAMPLE_COUNT) * pdf + 0.0001);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle), 0.0);
+            color = color + textureSampleLevel(t_environment, s_environment, l, lod).rgb * n_dot_l;
+            total_weight = total_weight + n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(total_weight, 0.0001), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // k for image based lighting
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// Scale and bias to F0 of the split sum approximation, indexed by
+// (n_dot_v, roughness)
+[[stage(fragment)]]
+fn fs_brdf(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let n_dot_v = in.tex_coords.x;
+    let roughness = in.tex_coords.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLE_COUNT; i = i + 1u) {
+        let h = importance_sample_ggx(hammersley(i, BRDF_SAMPLE_COUNT), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(h.z, 0.0);
+            let v_dot_h = max(dot(v, h), 0.0);
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale = scale + (1.0 - fc) * g_vis;
+            bias = bias + fc * g_vis;
+        }
+    }
+    return vec4<f32>(scale, bias, 0.0, 1.0) / vec4<f32>(f32(BRDF_SAMPLE_COUNT), f32(BRDF_SAMPLE_COUNT), 1.0, 1.0);
+}
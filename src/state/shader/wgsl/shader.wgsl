@@ -7,6 +7,8 @@ struct InstanceInput {
     [[location(9)]] normal_matrix_0: vec3<f32>;
     [[location(10)]] normal_matrix_1: vec3<f32>;
     [[location(11)]] normal_matrix_2: vec3<f32>;
+
+    [[location(12)]] texture_layer: u32;
 };
 
 // Vertex shader textured poly
@@ -35,6 +37,7 @@ struct VertexOutput {
     [[location(0)]] tex_coords: vec2<f32>;
     [[location(1)]] world_normal: vec3<f32>;
     [[location(2)]] world_position: vec3<f32>;
+    [[location(3), interpolate(flat)]] texture_layer: u32;
 };
 
 [[stage(vertex)]]
@@ -55,6 +58,7 @@ fn vs_main(
     );
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.texture_layer = instance.texture_layer;
     out.world_normal = normal_matrix * model.normal;
     var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
@@ -68,16 +72,31 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_diffuse: sampler;
+// Bound instead of t_diffuse by the texture array pipeline, see fs_array
+[[group(0), binding(2)]]
+var t_diffuse_array: texture_2d_array<f32>;
+
+// Image based lighting, see ibl.wgsl
+[[group(3), binding(0)]]
+var t_irradiance: texture_cube<f32>;
+[[group(3), binding(1)]]
+var t_prefiltered: texture_cube<f32>;
+[[group(3), binding(2)]]
+var t_brdf_lut: texture_2d<f32>;
+[[group(3), binding(3)]]
+var s_ibl: sampler;
+
+// Matches PREFILTER_MIP_LEVELS in ibl.rs
+let MAX_REFLECTION_LOD: f32 = 4.0;
+// Materials don't carry PBR parameters yet, everything is a rough dielectric
+let ROUGHNESS: f32 = 0.5;
+let F0: vec3<f32> = vec3<f32>(0.04, 0.04, 0.04);
+
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    
-    // ambient light
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
-
+fn shade(in: VertexOutput, object_color: vec4<f32>) -> vec4<f32> {
     // diffuse light
     let light_dir = normalize(light.position - in.world_position);
 
@@ -96,7 +115,30 @@ fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
 
     let specular_color = specular_strength * light.color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    // ambient light from the environment, split into diffuse irradiance and
+    // prefiltered specular reflections
+    let normal = normalize(in.world_normal);
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let fresnel = fresnel_schlick_roughness(n_dot_v, F0, ROUGHNESS);
+    let irradiance = textureSample(t_irradiance, s_ibl, normal).rgb;
+    let ambient_diffuse = (1.0 - fresnel) * irradiance * object_color.xyz;
+    let reflect_dir = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_ibl, reflect_dir, ROUGHNESS * MAX_REFLECTION_LOD).rgb;
+    let brdf = textureSample(t_brdf_lut, s_ibl, vec2<f32>(n_dot_v, ROUGHNESS)).rg;
+    let ambient_specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    let result = ambient_diffuse + ambient_specular + (diffuse_color + specular_color) * object_color.xyz;
 
     return vec4<f32>(result, object_color.a);
 }
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, textureSample(t_diffuse, s_diffuse, in.tex_coords));
+}
+
+// Every instance picks its material from a layer of the array
+[[stage(fragment)]]
+fn fs_array(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, textureSample(t_diffuse_array, s_diffuse, in.tex_coords, i32(in.texture_layer)));
+}
@@ -0,0 +1,34 @@
+// Draws the environment cubemap behind everything else
+struct Sky {
+    // Inverse of the view projection without the camera translation
+    inv_view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> sky: Sky;
+[[group(0), binding(1)]]
+var t_sky: texture_cube<f32>;
+[[group(0), binding(2)]]
+var s_sky: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+// One triangle covering the viewport on the far plane
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let world = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(world.xyz / world.w);
+    return textureSample(t_sky, s_sky, direction);
+}
@@ -0,0 +1,88 @@
+// Debug views of the scene geometry, see PolygonView in render_pipeline.rs.
+// vs_main and fs_line are drawn with a line or point polygon mode.
+// vs_barycentric and fs_barycentric draw wireframes on devices without line
+// polygon mode, from a mesh copy where every triangle has its own vertices.
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] barycentric: vec3<f32>;
+};
+
+let WIRE_COLOR: vec3<f32> = vec3<f32>(0.9, 0.9, 0.9);
+// In pixels
+let LINE_WIDTH: f32 = 1.0;
+
+fn clip_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = clip_position(model, instance);
+    out.barycentric = vec3<f32>(0.0);
+    return out;
+}
+
+// Only works for non indexed triangle lists, there the vertex index tells
+// which corner of its triangle a vertex is
+[[stage(vertex)]]
+fn vs_barycentric(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let corner = vertex_index % 3u;
+    var out: VertexOutput;
+    out.clip_position = clip_position(model, instance);
+    out.barycentric = vec3<f32>(
+        select(0.0, 1.0, corner == 0u),
+        select(0.0, 1.0, corner == 1u),
+        select(0.0, 1.0, corner == 2u),
+    );
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_line(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(WIRE_COLOR, 1.0);
+}
+
+// Keeps the pixels within LINE_WIDTH of an edge, the rest of the triangle is discarded
+[[stage(fragment)]]
+fn fs_barycentric(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let pixels = in.barycentric / max(fwidth(in.barycentric), vec3<f32>(0.0001));
+    let edge_distance = min(min(pixels.x, pixels.y), pixels.z);
+    // Fades out over one pixel for some antialiasing
+    let coverage = clamp(LINE_WIDTH + 0.5 - edge_distance, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(WIRE_COLOR, coverage);
+}
@@ -1,6 +1,17 @@
 use super::render_components::color_renderpass::ColorPass;
+use super::render_components::compressed;
 //use super::render_components::depth_renderpass::DepthPass;
 use super::render_components::texture::Texture;
+use super::render_components::render_target::{self, Blit, RenderTarget, RenderTargetDesc, TargetSize};
+use super::render_components::sampler::SamplerDesc;
+use super::render_components::render_pipeline::WireframeMethod;
+use super::assets::AssetManager;
+use super::loader::{AssetLoader, LoadedAsset};
+use crate::wasm::vfs;
+#[cfg(not(target_arch = "wasm32"))]
+use super::hot_reload::{self, FileWatcher};
+#[cfg(not(target_arch = "wasm32"))]
+use super::screenshot::{self, ScreenshotConfig};
 
 use wgpu::SurfaceTexture;
 use winit::{
@@ -10,8 +21,20 @@ use winit::{
 use iced_wgpu::{wgpu, Backend, Renderer, Settings, Viewport};
 use iced_winit::{conversion, futures, program, winit, Clipboard, Debug, Size, Color};
 
+// new_headless found no adapter at all, not even a software one
+#[derive(Debug)]
+pub struct NoAdapterError;
+
+impl std::fmt::Display for NoAdapterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No adapter found, not even a fallback one")
+    }
+}
+
+impl std::error::Error for NoAdapterError {}
+
 pub struct State {
-    pub surface: wgpu::Surface,
+    surface: Option<wgpu::Surface>,
     pub format: wgpu::TextureFormat,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
@@ -19,8 +42,18 @@ pub struct State {
     pub physical_size: winit::dpi::PhysicalSize<u32>,
     pub viewport: Viewport,
     pub color_pass: ColorPass,
+    pub assets: AssetManager,
+    pub loader: AssetLoader,
+    #[cfg(not(target_arch = "wasm32"))]
+    file_watcher: Option<FileWatcher>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub screenshot_config: ScreenshotConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    screenshot_requested: bool,
     //depth_pass: DepthPass,
-    depth_texture: Texture,
+    // The scene is drawn offscreen and blitted onto the frame
+    pub scene_target: RenderTarget,
+    blit: Blit,
 }
 
 impl State {
@@ -70,7 +103,7 @@ impl State {
                     .request_device(
                         &wgpu::DeviceDescriptor {
                             label: None,
-                            features: adapter_features & wgpu::Features::default(),
+                            features: adapter_features & (wgpu::Features::default() | WireframeMethod::FEATURES | compressed::FEATURES),
                             limits: needed_limits,
                         },
                         None,
@@ -93,35 +126,121 @@ impl State {
             &config,
         );
 
-        let color_pass = ColorPass::new(&device, &config, &queue).await;
-        //let depth_pass = DepthPass::new(&device, &config);
+        Self::with_device(Some(surface), device, queue, config, viewport).await
+    }
+
+    // Without a window nothing is presented, the scene is only drawn into
+    // scene_target. Any adapter will do, down to a software fallback, so this
+    // also runs on machines without a GPU.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let backend = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(backend);
+
+        let adapter = match wgpu::util::initialize_adapter_from_env_or_default(&instance, backend, None).await {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter: true,
+                    compatible_surface: None,
+                })
+                .await
+                .ok_or(NoAdapterError)?,
+        };
+        log::info!("Rendering headless on {:?}", adapter.get_info());
+
+        // Software and GL adapters often miss some of the default limits
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: adapter.features() & (wgpu::Features::default() | WireframeMethod::FEATURES | compressed::FEATURES),
+                    limits: adapter.limits(),
+                },
+                None,
+            )
+            .await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let viewport = Viewport::with_physical_size(Size::new(config.width, config.height), 1.0);
+
+        Self::with_device(None, device, queue, config, viewport).await
+    }
+
+    async fn with_device(
+        surface: Option<wgpu::Surface>,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        viewport: Viewport,
+    ) -> anyhow::Result<Self> {
+        vfs::mount_startup_assets().await?;
+        let mut assets = AssetManager::new();
+        let loader = AssetLoader::new();
+        let scene_desc = RenderTargetDesc::new("Scene Target", config.format)
+            .with_depth(Some(Texture::DEPTH_FORMAT))
+            .with_sample_count(render_target::sample_count_from_env());
+        let color_pass = ColorPass::new(&device, &config, &queue, &scene_desc, &mut assets, &loader);
+        //let depth_pass = DepthPass::new(&device, &config, &mut assets.samplers);
         
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let scene_target = RenderTarget::new(
+            &device,
+            scene_desc,
+            config.width,
+            config.height,
+            &mut assets.samplers,
+        );
+        let blit = Blit::new(&device, config.format, &mut assets.pipelines);
 
         Ok (Self {
             surface,
-            format,
+            format: config.format,
             device,
             queue,
+            physical_size: winit::dpi::PhysicalSize::new(config.width, config.height),
             config,
-            physical_size,
             viewport,
             color_pass,
+            assets,
+            loader,
+            #[cfg(not(target_arch = "wasm32"))]
+            file_watcher: hot_reload::enabled().then(|| {
+                FileWatcher::new(vec![hot_reload::source_data_dir(), hot_reload::source_shader_dir()])
+            }),
+            #[cfg(not(target_arch = "wasm32"))]
+            screenshot_config: ScreenshotConfig::from_env(),
+            #[cfg(not(target_arch = "wasm32"))]
+            screenshot_requested: false,
             //depth_pass,
-            depth_texture,
+            scene_target,
+            blit,
         })
     }
 
+    // Only headless states have no surface
+    pub fn surface(&self) -> &wgpu::Surface {
+        self.surface.as_ref().expect("Headless state has no surface")
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) -> bool{
         if new_size.width > 0 && new_size.height > 0 {
             self.physical_size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
 
-            //self.depth_pass.resize(&self.device, &self.config);
-            self.color_pass.resize(&self.device, &self.config);
-            self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            //self.depth_pass.resize(&self.device, &self.config, &mut self.assets.samplers);
+            self.color_pass.resize(&self.config);
+            self.scene_target.resize(&self.device, new_size.width, new_size.height, &mut self.assets.samplers);
 
             self.viewport = Viewport::with_physical_size(
                 Size::new(new_size.width, new_size.height),
@@ -135,17 +254,236 @@ impl State {
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.color_pass.input(event)
+        #[cfg(not(target_arch = "wasm32"))]
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F12),
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.screenshot_requested = true;
+            return true;
+        }
+        self.color_pass.input(event, &self.assets)
     }
 
     pub fn update(&mut self) {
-        self.color_pass.update(&self.queue);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.hot_reload();
+
+        for loaded in self.loader.poll() {
+            self.finish_load(loaded);
+        }
+        self.color_pass.update(&self.queue, &self.assets);
+    }
+
+    // Blocks until every queued asset is loaded and uploaded
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn finish_loading(&mut self) {
+        loop {
+            // Jobs send their result before they count as finished
+            let done = !self.loader.status().is_loading();
+            for loaded in self.loader.poll() {
+                self.finish_load(loaded);
+            }
+            if done && !self.loader.status().is_loading() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    // Uploads what the loader read in the background and hands it to its user
+    fn finish_load(&mut self, loaded: LoadedAsset) {
+        match loaded {
+            LoadedAsset::Model { file_path, file_name, data } => {
+                let path = file_path.join(&file_name);
+                let handle = self.assets.add_model_data(
+                    &file_path,
+                    &file_name,
+                    data,
+                    &self.device,
+                    &self.queue,
+                    &self.color_pass.texture_bind_group_layout,
+                    self.color_pass.vertex_layout,
+                );
+                match handle {
+                    Ok(handle) => {
+                        if !self.color_pass.model_loaded(&path, handle, &self.device, &self.queue, &self.assets) {
+                            self.assets.release_model(handle);
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to upload {:?}: {:?}", path, e);
+                        self.loader.report_failure(format!("{}: {}", path.display(), e));
+                    }
+                }
+            }
+            LoadedAsset::Cubemap { name, faces } => {
+                let texture = Texture::from_cube_faces(
+                    &self.device,
+                    &self.queue,
+                    &faces,
+                    Some(&name),
+                    &mut self.assets.samplers,
+                    SamplerDesc::default(),
+                );
+                match texture {
+                    Ok(texture) => self.color_pass.set_environment(&self.device, &self.queue, texture),
+                    Err(e) => {
+                        log::error!("Failed to upload {}: {:?}", name, e);
+                        self.loader.report_failure(format!("{}: {}", name, e));
+                    }
+                }
+            }
+            LoadedAsset::Texture { path, sampler_desc, data } => {
+                let handle = self.assets.add_texture_data(&path, &data, sampler_desc, &self.device, &self.queue);
+                match handle {
+                    Ok(handle) => {
+                        if !self.color_pass.texture_loaded(&path, handle, &self.device, &self.queue, &self.assets) {
+                            self.assets.release_texture(handle);
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to upload {:?}: {:?}", path, e);
+                        self.loader.report_failure(format!("{}: {}", path.display(), e));
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn hot_reload(&mut self) {
+        let changed = match &mut self.file_watcher {
+            Some(file_watcher) => file_watcher.poll(),
+            None => return,
+        };
+
+        for path in changed {
+            match self.reload_file(&path) {
+                Ok(true) => log::info!("Reloaded {:?}", path),
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to reload {:?}, keeping the previous version: {:?}", path, e),
+            }
+        }
+    }
+
+    // Returns false for files that nothing currently loaded depends on
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_file(&mut self, path: &std::path::Path) -> anyhow::Result<bool> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+        if extension == "wgsl" {
+            // Shaders are identified by their path relative to the shader directory
+            let shader = match path.strip_prefix(hot_reload::source_shader_dir()) {
+                Ok(shader) => shader,
+                Err(_) => return Ok(false),
+            };
+            let source = std::fs::read_to_string(path)?;
+            return self.color_pass.reload_shader(&self.device, &mut self.assets.pipelines, shader, &source);
+        }
+
+        // Assets are cached by their path relative to the data directory
+        let relative = match path.strip_prefix(hot_reload::source_data_dir()) {
+            Ok(relative) => relative,
+            Err(_) => return Ok(false),
+        };
+        let layout = &self.color_pass.texture_bind_group_layout;
+        let vertex_layout = self.color_pass.vertex_layout;
+
+        match extension.as_str() {
+            "png" | "jpg" | "jpeg" | "hdr" | "exr" | "ktx2" | "dds" => {
+                let reloaded = futures::executor::block_on(
+                    self.assets.reload_texture(relative, &self.device, &self.queue, layout),
+                )?;
+                if reloaded {
+                    self.color_pass.texture_reloaded(relative, &self.device, &self.queue, &self.assets);
+                }
+                Ok(reloaded)
+            }
+            "obj" | "mtl" => {
+                // A material library can be used by any model next to it
+                let models = self.assets.models.handles().into_iter().filter(|&handle| {
+                    self.assets.models.path(handle).is_some_and(|model_path| {
+                        if extension == "obj" {
+                            model_path == relative
+                        } else {
+                            model_path.parent() == relative.parent()
+                        }
+                    })
+                }).collect::<Vec<_>>();
+
+                for handle in &models {
+                    futures::executor::block_on(
+                        self.assets.reload_model(*handle, &self.device, &self.queue, layout, vertex_layout),
+                    )?;
+                }
+                Ok(!models.is_empty())
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // Reads the scene back without the overlay. At scale 1.0 that is the last
+    // rendered frame, other scales draw the scene again into a temporary target.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture(&mut self, scale: f32) -> anyhow::Result<image::RgbaImage> {
+        if scale == 1.0 {
+            return pollster::block_on(screenshot::read_target(&self.device, &self.queue, &self.scene_target));
+        }
+
+        let (width, height) = TargetSize::Scaled(scale).resolve(self.config.width, self.config.height);
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            anyhow::bail!("A {}x{} capture is outside the supported size of 1 to {}", width, height, max_size);
+        }
+
+        let desc = self.scene_target.desc.clone().with_size(TargetSize::Fixed { width, height });
+        let target = RenderTarget::new(&self.device, desc, self.config.width, self.config.height, &mut self.assets.samplers);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        self.color_pass.render(&target, &mut encoder, &self.assets);
+        self.queue.submit(Some(encoder.finish()));
+        pollster::block_on(screenshot::read_target(&self.device, &self.queue, &target))
+    }
+
+    // Captures at screenshot_config.scale and writes a timestamped PNG
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_screenshot(&mut self) -> anyhow::Result<std::path::PathBuf> {
+        let image = self.capture(self.screenshot_config.scale)?;
+        let path = self.screenshot_config.next_path();
+        screenshot::save_png(&image, &path)?;
+        Ok(path)
+    }
+
+    // Handles F12, called once the frame is submitted so the scene target holds it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_requested_screenshot(&mut self) {
+        if !std::mem::take(&mut self.screenshot_requested) {
+            return;
+        }
+        match self.save_screenshot() {
+            Ok(path) => log::info!("Saved screenshot to {:?}", path),
+            Err(e) => log::error!("Failed to save screenshot: {:?}", e),
+        }
+    }
+
+    // Draws into scene_target only, without presenting it
+    pub fn render_scene(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.color_pass.render(&self.scene_target, encoder, &self.assets);
     }
 
     pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, frame: &SurfaceTexture) -> Result<(), wgpu::SurfaceError> {
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        self.color_pass.render(&view, encoder, &self.depth_texture);
+        self.render_scene(encoder);
+        self.blit.draw(&self.device, encoder, &mut self.scene_target, &view);
         //self.depth_pass.render(&view, encoder);
 
         Ok(())
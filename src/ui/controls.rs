@@ -1,29 +1,39 @@
 use iced_wgpu::Renderer;
 use iced_winit::widget::slider::{self, Slider};
 use iced_winit::widget::text_input::{self, TextInput};
-use iced_winit::widget::{Column, Row, Text};
+use iced_winit::widget::{Column, Radio, Row, Text};
 use iced_winit::{Alignment, Color, Command, Element, Length, Program};
 use iced::{button, Button};
 
 use iced_aw::color_picker::{self, ColorPicker};
 
+use crate::state::loader::LoadStatus;
+use crate::state::render_components::skybox::Background;
+use crate::state::render_components::primitives::Shape;
+
 pub struct Controls {
     state: color_picker::State,
     button_state: button::State,
     background_color: Color,
+    background: Background,
+    shape: Option<Shape>,
     text: String,
     sliders: [slider::State; 3],
     text_input: text_input::State,
+    load_status: LoadStatus,
 }
 
 #[derive(Debug, Clone)]
 #[allow(clippy::enum_variant_names)]
 pub enum Message {
     BackgroundColorChanged(Color),
+    BackgroundChanged(Background),
+    ShapeChanged(Option<Shape>),
     TextChanged(String),
     ChooseColor,
     SubmitColor(Color),
     CancelColor,
+    LoadStatusChanged(LoadStatus),
 }
 
 impl Controls {
@@ -32,15 +42,26 @@ impl Controls {
             state: color_picker::State::new(),
             button_state: button::State::new(),
             background_color: Color::BLACK,
+            background: Background::default(),
+            shape: None,
             text: Default::default(),
             sliders: Default::default(),
             text_input: Default::default(),
+            load_status: Default::default(),
         }
     }
 
     pub fn background_color(&self) -> Color {
         self.background_color
     }
+
+    pub fn background(&self) -> Background {
+        self.background
+    }
+
+    pub fn shape(&self) -> Option<Shape> {
+        self.shape
+    }
 }
 
 impl Program for Controls {
@@ -52,6 +73,12 @@ impl Program for Controls {
             Message::BackgroundColorChanged(color) => {
                 self.background_color = color;
             }
+            Message::BackgroundChanged(background) => {
+                self.background = background;
+            }
+            Message::ShapeChanged(shape) => {
+                self.shape = shape;
+            }
             Message::TextChanged(text) => {
                 self.text = text;
             }
@@ -65,6 +92,9 @@ impl Program for Controls {
             Message::CancelColor => {
                 self.state.show(false);
             }
+            Message::LoadStatusChanged(load_status) => {
+                self.load_status = load_status;
+            }
         }
 
         Command::none()
@@ -94,6 +124,53 @@ impl Program for Controls {
                 .color(Color::WHITE),
         );
 
+        let background_row = Row::new()
+            .spacing(20)
+            .push(Radio::new(
+                Background::ClearColor,
+                "Clear color",
+                Some(self.background),
+                Message::BackgroundChanged,
+            ).size(16).text_size(16))
+            .push(Radio::new(
+                Background::Skybox,
+                "Skybox",
+                Some(self.background),
+                Message::BackgroundChanged,
+            ).size(16).text_size(16));
+
+        let shape_row = Shape::ALL.iter().fold(
+            Row::new()
+                .spacing(10)
+                .push(Radio::new(None, "Model", Some(self.shape), Message::ShapeChanged).size(14).text_size(14)),
+            |row, &shape| {
+                row.push(Radio::new(Some(shape), shape.name(), Some(self.shape), Message::ShapeChanged).size(14).text_size(14))
+            },
+        );
+
+        let status = &self.load_status;
+        let mut load_status = Column::new().spacing(5);
+        if status.files_requested > 0 || status.is_loading() {
+            load_status = load_status.push(
+                Text::new(format!(
+                    "{} assets: {}/{} files, {:.1} KB",
+                    if status.is_loading() { "Loading" } else { "Loaded" },
+                    status.files_loaded,
+                    status.files_requested,
+                    status.bytes_loaded as f32 / 1024.0,
+                ))
+                .size(14)
+                .color(Color::WHITE),
+            );
+        }
+        for failure in &status.failures {
+            load_status = load_status.push(
+                Text::new(format!("Failed: {}", failure))
+                    .size(14)
+                    .color(Color::from_rgb(1.0, 0.3, 0.3)),
+            );
+        }
+
         let sliders = Row::new()
             .width(Length::Units(500))
             .spacing(20)
@@ -137,6 +214,9 @@ impl Program for Controls {
                         Column::new()
                             .padding(10)
                             .spacing(10)
+                            .push(load_status)
+                            .push(shape_row)
+                            .push(background_row)
                             .push(color_picker_row)
                             .push(
                                 Text::new("Background color")
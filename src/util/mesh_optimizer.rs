@@ -0,0 +1,319 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::state::render_components::model::ModelVertex;
+
+// Size of the simulated FIFO cache used for the ACMR statistic
+pub const ACMR_CACHE_SIZE: usize = 16;
+
+// Tuning values from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation"
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRI_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Copy, Clone, Debug)]
+pub struct MeshStats {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    // Average cache miss ratio: transformed vertices per triangle, 0.5 is ideal, 3.0 is worst
+    pub acmr: f32,
+}
+
+impl MeshStats {
+    pub fn new(vertex_count: usize, indices: &[u32]) -> Self {
+        Self {
+            vertex_count,
+            triangle_count: indices.len() / 3,
+            acmr: acmr(indices, ACMR_CACHE_SIZE),
+        }
+    }
+}
+
+// Runs all passes in order and returns the stats from before and after
+pub fn optimize_mesh(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (Vec<ModelVertex>, Vec<u32>, MeshStats, MeshStats) {
+    let before = MeshStats::new(vertices.len(), indices);
+
+    let (vertices, indices) = weld_vertices(vertices, indices);
+    let indices = optimize_vertex_cache(&indices, vertices.len());
+    let (vertices, indices) = optimize_vertex_fetch(&vertices, &indices);
+
+    let after = MeshStats::new(vertices.len(), &indices);
+    (vertices, indices, before, after)
+}
+
+// Merges vertices whose attributes are bit-identical
+pub fn weld_vertices(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    fn key(vertex: &ModelVertex) -> [u32; 8] {
+        let mut key = [0; 8];
+        let values = vertex.position.iter()
+            .chain(vertex.tex_coords.iter())
+            .chain(vertex.normal.iter());
+        for (k, v) in key.iter_mut().zip(values) {
+            // Treat -0.0 and 0.0 as the same value
+            *k = if *v == 0.0 { 0 } else { v.to_bits() };
+        }
+        key
+    }
+
+    let mut lookup = HashMap::with_capacity(vertices.len());
+    let mut welded = Vec::with_capacity(vertices.len());
+    let remap = vertices.iter()
+        .map(|vertex| {
+            *lookup.entry(key(vertex)).or_insert_with(|| {
+                welded.push(*vertex);
+                (welded.len() - 1) as u32
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let indices = indices.iter().map(|&i| remap[i as usize]).collect();
+    (welded, indices)
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices get a fixed score so the same triangle
+        // isn't favoured again just because its vertices are hot
+        Some(position) if position < 3 => LAST_TRI_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    // Boost vertices with few triangles left so they get finished off
+    let valence_boost = VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+// Reorders triangles so that consecutive triangles reuse recently transformed vertices
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let triangle = |t: usize| [indices[t * 3], indices[t * 3 + 1], indices[t * 3 + 2]];
+
+    // Triangles that still have to be emitted, per vertex
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for t in 0..triangle_count {
+        for v in triangle(t) {
+            vertex_triangles[v as usize].push(t);
+        }
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores = vertex_triangles.iter()
+        .map(|triangles| vertex_score(None, triangles.len()))
+        .collect::<Vec<_>>();
+    let mut triangle_scores = (0..triangle_count)
+        .map(|t| triangle(t).iter().map(|&v| vertex_scores[v as usize]).sum::<f32>())
+        .collect::<Vec<_>>();
+    let mut triangle_added = vec![false; triangle_count];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut best_triangle = None;
+    let mut scan_cursor = 0;
+
+    while output.len() < triangle_count * 3 {
+        // Nothing useful in the cache, restart at the next unused triangle
+        let t = match best_triangle {
+            Some(t) => t,
+            None => {
+                while triangle_added[scan_cursor] {
+                    scan_cursor += 1;
+                }
+                scan_cursor
+            }
+        };
+
+        triangle_added[t] = true;
+        output.extend_from_slice(&triangle(t));
+
+        for v in triangle(t) {
+            let triangles = &mut vertex_triangles[v as usize];
+            if let Some(i) = triangles.iter().position(|&other| other == t) {
+                triangles.swap_remove(i);
+            }
+            if let Some(i) = cache.iter().position(|&cached| cached == v) {
+                cache.remove(i);
+            }
+            cache.insert(0, v);
+        }
+
+        let evicted = if cache.len() > CACHE_SIZE {
+            cache.split_off(CACHE_SIZE)
+        } else {
+            Vec::new()
+        };
+        for &v in &evicted {
+            cache_position[v as usize] = None;
+        }
+        for (i, &v) in cache.iter().enumerate() {
+            cache_position[v as usize] = Some(i);
+        }
+
+        for &v in cache.iter().chain(evicted.iter()) {
+            let v = v as usize;
+            let score = vertex_score(cache_position[v], vertex_triangles[v].len());
+            let delta = score - vertex_scores[v];
+            vertex_scores[v] = score;
+            for &other in &vertex_triangles[v] {
+                triangle_scores[other] += delta;
+            }
+        }
+
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+        for &v in &cache {
+            for &other in &vertex_triangles[v as usize] {
+                if triangle_scores[other] > best_score {
+                    best_score = triangle_scores[other];
+                    best_triangle = Some(other);
+                }
+            }
+        }
+    }
+
+    output
+}
+
+// Renumbers vertices in the order they are first referenced, unreferenced vertices are dropped
+pub fn optimize_vertex_fetch(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+
+    let indices = indices.iter()
+        .map(|&i| {
+            if remap[i as usize] == u32::MAX {
+                remap[i as usize] = reordered.len() as u32;
+                reordered.push(vertices[i as usize]);
+            }
+            remap[i as usize]
+        })
+        .collect();
+
+    (reordered, indices)
+}
+
+// Simulates a FIFO post-transform cache and returns the misses per triangle
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &i in indices {
+        if !cache.contains(&i) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(i);
+        }
+    }
+
+    misses as f32 / triangle_count as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32) -> ModelVertex {
+        ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [x, y],
+            normal: [0.0, 0.0, 1.0],
+        }
+    }
+
+    // n x n quads with two triangles each, every triangle has its own vertices
+    fn unwelded_grid(n: usize) -> Vec<ModelVertex> {
+        let mut vertices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let (x, y) = (x as f32, y as f32);
+                let corners = [vertex(x, y), vertex(x + 1.0, y), vertex(x + 1.0, y + 1.0), vertex(x, y + 1.0)];
+                vertices.extend_from_slice(&[corners[0], corners[1], corners[2], corners[0], corners[2], corners[3]]);
+            }
+        }
+        vertices
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect::<Vec<_>>();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn weld_merges_duplicated_quad_corners() {
+        let vertices = unwelded_grid(4);
+        let indices = (0..vertices.len() as u32).collect::<Vec<_>>();
+
+        let (welded, welded_indices) = weld_vertices(&vertices, &indices);
+
+        assert_eq!(welded.len(), 5 * 5);
+        assert_eq!(welded_indices.len(), indices.len());
+        for (&before, &after) in indices.iter().zip(&welded_indices) {
+            assert!((after as usize) < welded.len());
+            assert_eq!(vertices[before as usize].position, welded[after as usize].position);
+        }
+    }
+
+    #[test]
+    fn vertex_cache_keeps_triangles_and_does_not_worsen_acmr() {
+        let vertices = unwelded_grid(16);
+        let indices = (0..vertices.len() as u32).collect::<Vec<_>>();
+        let (welded, welded_indices) = weld_vertices(&vertices, &indices);
+
+        // Scramble the triangle order so there is something to optimize
+        let mut triangles = welded_indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect::<Vec<_>>();
+        let mut seed = 12345u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            triangles.swap(i, seed as usize % (i + 1));
+        }
+        let scrambled = triangles.concat();
+
+        let optimized = optimize_vertex_cache(&scrambled, welded.len());
+
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&scrambled));
+        assert!(acmr(&optimized, ACMR_CACHE_SIZE) <= acmr(&scrambled, ACMR_CACHE_SIZE));
+    }
+
+    #[test]
+    fn vertex_fetch_orders_by_first_use() {
+        let vertices = [vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(2.0, 0.0), vertex(3.0, 0.0)];
+        let indices = [2, 0, 3, 2, 3, 0];
+
+        let (reordered, indices) = optimize_vertex_fetch(&vertices, &indices);
+
+        // Vertex 1 is never referenced and gets dropped
+        let xs = reordered.iter().map(|v| v.position[0]).collect::<Vec<_>>();
+        assert_eq!(xs, [2.0, 0.0, 3.0]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 1]);
+    }
+
+    #[test]
+    fn acmr_counts_fifo_misses_per_triangle() {
+        // Two triangles sharing an edge transform four vertices
+        assert_eq!(acmr(&[0, 1, 2, 2, 1, 3], 16), 2.0);
+        // With room for three vertices the first triangle is evicted before it's reused
+        let indices = [0, 1, 2, 3, 4, 5, 0, 1, 2];
+        assert_eq!(acmr(&indices, 3), 3.0);
+        assert_eq!(acmr(&indices, 16), 2.0);
+        assert_eq!(acmr(&[], 16), 0.0);
+    }
+}
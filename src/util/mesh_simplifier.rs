@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+
+use crate::state::render_components::model::ModelVertex;
+use crate::util::mesh_optimizer::optimize_vertex_cache;
+
+// Fraction of the full triangle count every generated LOD aims for
+pub const LOD_TRIANGLE_RATIOS: [f32; 4] = [1.0, 0.5, 0.25, 0.125];
+// Largest allowed deviation from the original surface, relative to the mesh extent
+pub const LOD_MAX_ERROR: f32 = 0.1;
+
+// Symmetric 4x4 error quadric, stored as the upper triangle
+#[derive(Copy, Clone, Default)]
+struct Quadric {
+    a2: f64, ab: f64, ac: f64, ad: f64,
+    b2: f64, bc: f64, bd: f64,
+    c2: f64, cd: f64,
+    d2: f64,
+}
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Self {
+        Self {
+            a2: a * a * weight, ab: a * b * weight, ac: a * c * weight, ad: a * d * weight,
+            b2: b * b * weight, bc: b * c * weight, bd: b * d * weight,
+            c2: c * c * weight, cd: c * d * weight,
+            d2: d * d * weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.a2 += other.a2; self.ab += other.ab; self.ac += other.ac; self.ad += other.ad;
+        self.b2 += other.b2; self.bc += other.bc; self.bd += other.bd;
+        self.c2 += other.c2; self.cd += other.cd;
+        self.d2 += other.d2;
+    }
+
+    // Weighted sum of squared distances from p to the accumulated planes
+    fn error(&self, p: [f32; 3]) -> f64 {
+        let (x, y, z) = (p[0] as f64, p[1] as f64, p[2] as f64);
+        self.a2 * x * x + 2.0 * self.ab * x * y + 2.0 * self.ac * x * z + 2.0 * self.ad * x
+            + self.b2 * y * y + 2.0 * self.bc * y * z + 2.0 * self.bd * y
+            + self.c2 * z * z + 2.0 * self.cd * z
+            + self.d2
+    }
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn triangle_normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    cross(sub(b, a), sub(c, a))
+}
+
+// Returns simplified indices into the unchanged vertex slice. Collapses edges
+// onto one of their end points in order of quadric error until the index count
+// drops to target_index_count or every remaining collapse exceeds target_error
+// (relative to the mesh extent).
+//
+// Vertices that share a position but differ in their attributes (seams) move
+// together: a position can only collapse along an edge that every one of its
+// vertices is attached to, so seams stay closed and can only shorten along
+// themselves. Vertices on open borders are never moved so the silhouette stays
+// intact, and collapses that would make the mesh non-manifold are skipped.
+pub fn simplify(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    target_index_count: usize,
+    target_error: f32,
+) -> Vec<u32> {
+    let vertex_count = vertices.len();
+    let position = |v: u32| vertices[v as usize].position;
+
+    // Vertices that only differ in their attributes share one position id
+    let mut position_lookup = HashMap::new();
+    let position_ids = vertices.iter()
+        .map(|vertex| {
+            let key = vertex.position.map(|p| if p == 0.0 { 0 } else { p.to_bits() });
+            let next = position_lookup.len() as u32;
+            *position_lookup.entry(key).or_insert(next)
+        })
+        .collect::<Vec<_>>();
+    let position_count = position_lookup.len();
+    let position_id = |v: u32| position_ids[v as usize] as usize;
+    let mut points = vec![[0.0; 3]; position_count];
+    for (vertex, &id) in vertices.iter().zip(&position_ids) {
+        points[id as usize] = vertex.position;
+    }
+
+    // Open borders and non-manifold edges
+    let mut locked = vec![false; position_count];
+    for (&(a, b), opposites) in &edge_opposites(indices, &position_ids) {
+        if opposites.len() != 2 {
+            locked[a] = true;
+            locked[b] = true;
+        }
+    }
+
+    let mut quadrics = vec![Quadric::default(); position_count];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [position(triangle[0]), position(triangle[1]), position(triangle[2])];
+        let normal = triangle_normal(a, b, c);
+        let length = dot(normal, normal).sqrt();
+        if length <= f32::EPSILON {
+            continue;
+        }
+        let n = normal.map(|x| (x / length) as f64);
+        let d = -(n[0] * a[0] as f64 + n[1] * a[1] as f64 + n[2] * a[2] as f64);
+        // Weight by area so large faces resist being flattened
+        let quadric = Quadric::from_plane(n[0], n[1], n[2], d, length as f64 * 0.5);
+        for &v in triangle {
+            quadrics[position_id(v)].add(&quadric);
+        }
+    }
+
+    let (min, max) = vertices.iter().fold(
+        ([f32::MAX; 3], [f32::MIN; 3]),
+        |(min, max), v| (
+            [min[0].min(v.position[0]), min[1].min(v.position[1]), min[2].min(v.position[2])],
+            [max[0].max(v.position[0]), max[1].max(v.position[1]), max[2].max(v.position[2])],
+        ),
+    );
+    let extent = dot(sub(max, min), sub(max, min)).sqrt();
+    let error_limit = (target_error * extent) as f64 * (target_error * extent) as f64;
+
+    let mut indices = indices.to_vec();
+    while indices.len() > target_index_count {
+        let opposites = edge_opposites(&indices, &position_ids);
+        let mut neighbors = vec![Vec::new(); position_count];
+        for &(a, b) in opposites.keys() {
+            neighbors[a].push(b);
+            neighbors[b].push(a);
+        }
+
+        let mut candidates = Vec::new();
+        for &(a, b) in opposites.keys() {
+            for (from, to) in [(a, b), (b, a)] {
+                if !locked[from] {
+                    candidates.push((quadrics[from].error(points[to]), from, to));
+                }
+            }
+        }
+        // Ties are common on flat surfaces, break them by position id so the
+        // result doesn't depend on the hash map's order
+        candidates.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then((a.1, a.2).cmp(&(b.1, b.2)))
+        });
+
+        // Triangles around every position, and the vertices there still in use
+        let mut position_triangles = vec![Vec::new(); position_count];
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                position_triangles[position_id(v)].push(t);
+            }
+        }
+        let triangle = |t: usize| [indices[t * 3], indices[t * 3 + 1], indices[t * 3 + 2]];
+
+        // Collapses within one pass must not share triangles, otherwise the
+        // flip test below would look at stale positions
+        let mut touched = vec![false; position_count];
+        let mut remap = (0..vertex_count as u32).collect::<Vec<_>>();
+        let triangles_to_remove = (indices.len() - target_index_count) / 3;
+        let mut removed = 0;
+
+        for (cost, from, to) in candidates {
+            if cost > error_limit || removed >= triangles_to_remove.max(1) {
+                break;
+            }
+            if touched[from] || touched[to] {
+                continue;
+            }
+
+            // Link condition: the only positions next to both ends are the
+            // tips of the two triangles on the edge
+            let shared_neighbors = neighbors[from].iter().filter(|n| neighbors[to].contains(n)).count();
+            if shared_neighbors != opposites[&(from.min(to), from.max(to))].len() {
+                continue;
+            }
+
+            // Every vertex at `from` moves to the vertex at `to` it shares a
+            // triangle with. Seam vertices without one would tear the seam open.
+            let mut moves = Vec::new();
+            let mut seam_intact = true;
+            for &t in &position_triangles[from] {
+                let corners = triangle(t);
+                let v = corners.into_iter().find(|&v| position_id(v) == from).unwrap();
+                if moves.iter().any(|&(moved, _)| moved == v) {
+                    continue;
+                }
+                let target = position_triangles[from]
+                    .iter()
+                    .flat_map(|&other| {
+                        let corners = triangle(other);
+                        corners.contains(&v).then_some(corners)
+                    })
+                    .flatten()
+                    .find(|&w| position_id(w) == to);
+                match target {
+                    Some(w) => moves.push((v, w)),
+                    None => {
+                        seam_intact = false;
+                        break;
+                    }
+                }
+            }
+            if !seam_intact {
+                continue;
+            }
+
+            let flips = position_triangles[from].iter().any(|&t| {
+                let corners = triangle(t);
+                if corners.iter().any(|&v| position_id(v) == to) {
+                    return false;
+                }
+                let before = corners.map(position);
+                let after = corners.map(|v| if position_id(v) == from { points[to] } else { position(v) });
+                dot(
+                    triangle_normal(before[0], before[1], before[2]),
+                    triangle_normal(after[0], after[1], after[2]),
+                ) <= 0.0
+            });
+            if flips {
+                continue;
+            }
+
+            for &t in &position_triangles[from] {
+                let corners = triangle(t);
+                for v in corners {
+                    touched[position_id(v)] = true;
+                }
+                if corners.iter().any(|&v| position_id(v) == to) {
+                    removed += 1;
+                }
+            }
+            for (v, w) in moves {
+                remap[v as usize] = w;
+            }
+            let merged = quadrics[from];
+            quadrics[to].add(&merged);
+        }
+
+        if removed == 0 {
+            break;
+        }
+
+        indices = indices
+            .chunks_exact(3)
+            .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+            .filter(|t| {
+                let [a, b, c] = t.map(position_id);
+                a != b && b != c && a != c
+            })
+            .flatten()
+            .collect();
+    }
+
+    indices
+}
+
+// Position id pairs of every edge, mapped to the position opposite of the
+// edge in each triangle using it
+fn edge_opposites(indices: &[u32], position_ids: &[u32]) -> HashMap<(usize, usize), Vec<usize>> {
+    let mut opposites = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        for k in 0..3 {
+            let a = position_ids[triangle[k] as usize] as usize;
+            let b = position_ids[triangle[(k + 1) % 3] as usize] as usize;
+            let c = position_ids[triangle[(k + 2) % 3] as usize] as usize;
+            opposites.entry((a.min(b), a.max(b))).or_insert_with(Vec::new).push(c);
+        }
+    }
+    opposites
+}
+
+// Builds the index lists for every level in LOD_TRIANGLE_RATIOS, starting with
+// the given indices as LOD 0. Stops early once simplification stalls.
+pub fn generate_lods(vertices: &[ModelVertex], indices: &[u32]) -> Vec<Vec<u32>> {
+    let mut lods = vec![indices.to_vec()];
+
+    for ratio in &LOD_TRIANGLE_RATIOS[1..] {
+        let target_index_count = ((indices.len() / 3) as f32 * ratio) as usize * 3;
+        let previous = lods.last().unwrap();
+        let lod = simplify(vertices, previous, target_index_count, LOD_MAX_ERROR);
+        if lod.is_empty() || lod.len() >= previous.len() {
+            break;
+        }
+        lods.push(optimize_vertex_cache(&lod, vertices.len()));
+    }
+
+    lods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::render_components::primitives;
+
+    // Cube with every face split into n x n quads and its own vertices, like
+    // a model with flat normals
+    fn tessellated_cube(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+        ];
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (normal, u, v) in faces {
+            let first = vertices.len() as u32;
+            for j in 0..=n {
+                for i in 0..=n {
+                    let (s, t) = (i as f32 / n as f32, j as f32 / n as f32);
+                    let position = [0, 1, 2].map(|k| normal[k] + u[k] * (2.0 * s - 1.0) + v[k] * (2.0 * t - 1.0));
+                    vertices.push(ModelVertex { position, tex_coords: [s, t], normal });
+                }
+            }
+            for j in 0..n {
+                for i in 0..n {
+                    let a = first + j * (n + 1) + i;
+                    let (b, c, d) = (a + 1, a + n + 2, a + n + 1);
+                    indices.extend_from_slice(&[a, b, c, a, c, d]);
+                }
+            }
+        }
+        (vertices, indices)
+    }
+
+    // Closed, consistently wound and free of degenerate triangles, looking at
+    // positions so seams count as connected
+    fn assert_manifold(vertices: &[ModelVertex], indices: &[u32]) {
+        let key = |v: u32| vertices[v as usize].position.map(|p| if p == 0.0 { 0 } else { p.to_bits() });
+        let mut directed_edges = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            for k in 0..3 {
+                let (a, b) = (key(triangle[k]), key(triangle[(k + 1) % 3]));
+                assert_ne!(a, b, "degenerate triangle");
+                *directed_edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &directed_edges {
+            assert_eq!(count, 1, "edge used twice in the same direction");
+            assert_eq!(directed_edges.get(&(b, a)), Some(&1), "open or non-manifold edge");
+        }
+    }
+
+    fn assert_lods_shrink(vertices: &[ModelVertex], indices: &[u32]) {
+        let lods = generate_lods(vertices, indices);
+        assert_eq!(lods.len(), LOD_TRIANGLE_RATIOS.len());
+        for pair in lods.windows(2) {
+            assert!(pair[1].len() < pair[0].len(), "{} -> {} indices", pair[0].len(), pair[1].len());
+        }
+        for lod in &lods {
+            assert!(lod.iter().all(|&i| (i as usize) < vertices.len()));
+            assert_manifold(vertices, lod);
+        }
+    }
+
+    #[test]
+    fn flat_shaded_cube_loses_triangles_at_every_lod() {
+        let (vertices, indices) = tessellated_cube(8);
+        assert_manifold(&vertices, &indices);
+        assert_lods_shrink(&vertices, &indices);
+    }
+
+    #[test]
+    fn sphere_with_uv_seam_loses_triangles_at_every_lod() {
+        let sphere = primitives::uv_sphere(1.0, 32, 16);
+        assert_manifold(&sphere.vertices, &sphere.indices);
+        assert_lods_shrink(&sphere.vertices, &sphere.indices);
+    }
+
+    #[test]
+    fn shipped_cube_loses_triangles_at_every_lod() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data/models/cube/cube.obj");
+        let options = tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() };
+        let (models, _) = tobj::load_obj(path, &options).unwrap();
+
+        for model in models {
+            let mesh = model.mesh;
+            let vertices = (0..mesh.positions.len() / 3)
+                .map(|i| ModelVertex {
+                    position: [mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]],
+                    tex_coords: [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]],
+                    normal: [mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]],
+                })
+                .collect::<Vec<_>>();
+            let (vertices, indices, _, _) = crate::util::mesh_optimizer::optimize_mesh(&vertices, &mesh.indices);
+            assert_lods_shrink(&vertices, &indices);
+        }
+    }
+
+    #[test]
+    fn lods_are_the_same_every_time() {
+        let (vertices, indices) = tessellated_cube(8);
+        let lods = generate_lods(&vertices, &indices);
+        for _ in 0..4 {
+            assert_eq!(generate_lods(&vertices, &indices), lods);
+        }
+    }
+
+    #[test]
+    fn cube_corners_stay_in_place() {
+        let (vertices, indices) = tessellated_cube(4);
+        let lod = simplify(&vertices, &indices, 0, LOD_MAX_ERROR);
+
+        // Everything flat collapses away, only the corners can't move
+        let mut corners = lod.iter().map(|&i| vertices[i as usize].position.map(f32::to_bits)).collect::<Vec<_>>();
+        corners.sort_unstable();
+        corners.dedup();
+        assert_eq!(corners.len(), 8);
+        assert_eq!(lod.len(), 12 * 3);
+        assert_manifold(&vertices, &lod);
+    }
+}
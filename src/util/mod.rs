@@ -1,2 +1,4 @@
 pub mod toggle_bool;
-pub mod math_funcs;
\ No newline at end of file
+pub mod math_funcs;
+pub mod mesh_optimizer;
+pub mod mesh_simplifier;
\ No newline at end of file
@@ -0,0 +1,9 @@
+// Generated by build.rs from the contents of data/
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+pub fn get(key: &str) -> Option<&'static [u8]> {
+    EMBEDDED_ASSETS
+        .binary_search_by_key(&key, |(path, _)| *path)
+        .ok()
+        .map(|i| EMBEDDED_ASSETS[i].1)
+}
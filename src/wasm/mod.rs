@@ -1 +1,4 @@
-pub mod resources;
\ No newline at end of file
+#[cfg(feature = "embedded-assets")]
+pub mod embedded;
+pub mod resources;
+pub mod vfs;
@@ -1,77 +1,40 @@
 use std::{io::{BufReader, Cursor}};
 
-use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
-
-use crate::state::render_components::{model, texture};
-
-#[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let base = reqwest::Url::parse(&format!(
-        "{}/{}/",
-        location.origin().unwrap(),
-        option_env!("RES_PATH").unwrap_or("data"),
-    )).unwrap();
-    base.join(file_name).unwrap()
-}
+use crate::state::assets::AssetManager;
+use crate::state::loader::LoadProgress;
+use crate::state::render_components::{cubemap, model, texture};
+use crate::state::render_components::sampler::{self, SamplerDesc};
+use crate::util::{mesh_optimizer, mesh_simplifier};
+use super::vfs;
 
 pub async fn load_string(file_path: &std::path::Path, file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            log::warn!("Creating url for {}", file_name);
-            let url = format_url(file_path.join(file_name).to_str().unwrap());
-            log::warn!("Making request for {}", url);
-            let res = reqwest::get(url).await?;
-            log::warn!("Received response");
-            let txt = res.text().await?;
-            log::warn!("Finished request");
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("data")
-                .join(file_path)
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
-        }
-    }
-
-    Ok(txt)
+    let data = vfs::read(&file_path.join(file_name)).await?;
+    Ok(String::from_utf8(data)?)
 }
 
 pub async fn load_binary(file_path: &std::path::Path, file_name: &str) -> anyhow::Result<Vec<u8>> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_path.join(file_name).to_str().unwrap());
-            let data = reqwest::get(url)
-                .await?
-                .bytes()
-                .await?
-                .to_vec();
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("data")
-                .join(file_path)
-                .join(file_name);
-            let data = std::fs::read(path)?;
-        }
-    }
+    vfs::read(&file_path.join(file_name)).await
+}
 
-    Ok(data)
+// Everything load_model needs before touching the GPU. Reading, parsing and
+// decoding happen here so it can run away from the render thread.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
 }
 
-pub async fn load_texture(
-    file_path: &std::path::Path,
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_path, file_name).await?;
-    texture::Texture::from_bytes(
-        device, 
-        queue, 
-        &data, 
-        file_name)
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<model::ModelVertex>,
+    pub lods: Vec<Vec<u32>>,
+    pub material: usize,
+}
+
+pub struct MaterialData {
+    pub name: String,
+    pub diffuse_texture: String,
+    pub diffuse_sampler: SamplerDesc,
+    pub diffuse_data: texture::TextureData,
 }
 
 pub async fn load_model(
@@ -80,8 +43,19 @@ pub async fn load_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    vertex_layout: model::ModelVertexLayout,
+    assets: &mut AssetManager,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_path, file_name).await?;
+    let data = read_model(file_path, file_name, &LoadProgress::default()).await?;
+    upload_model(data, file_path, device, queue, layout, vertex_layout, assets)
+}
+
+pub async fn read_model(
+    file_path: &std::path::Path,
+    file_name: &str,
+    progress: &LoadProgress,
+) -> anyhow::Result<ModelData> {
+    let obj_text = String::from_utf8(progress.read(&file_path.join(file_name)).await?)?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
@@ -93,7 +67,10 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(file_path, &p).await.unwrap();
+            let mat_text = progress.read(&file_path.join(&p)).await
+                .ok()
+                .and_then(|data| String::from_utf8(data).ok())
+                .ok_or(tobj::LoadError::OpenFileFailed)?;
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         },
     )
@@ -101,33 +78,25 @@ pub async fn load_model(
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = load_texture(file_path, &m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
+        // tobj leaves texture options like -clamp in front of the file name
+        let (diffuse_texture, diffuse_sampler) = sampler::parse_mtl_texture(&m.diffuse_texture);
+        let data = progress.read(&file_path.join(&diffuse_texture)).await?;
+        materials.push(MaterialData {
             name: m.name,
+            diffuse_data: texture::load_texture_data(&data)?,
             diffuse_texture,
-            bind_group,
-        })
+            diffuse_sampler,
+        });
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let vertex_count = m.mesh.positions.len() / 3;
+            if m.mesh.texcoords.len() < vertex_count * 2 || m.mesh.normals.len() < vertex_count * 3 {
+                anyhow::bail!("{} in {} needs tex coords and normals for every vertex", m.name, file_name);
+            }
+            let vertices = (0..vertex_count)
                 .map(|i| model::ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
@@ -143,27 +112,101 @@ pub async fn load_model(
                 })
                 .collect::<Vec<_>>();
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_path)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_path)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: String::from(file_path.to_str().unwrap()),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+            let (vertices, indices, before, after) = mesh_optimizer::optimize_mesh(&vertices, &m.mesh.indices);
+            log::info!(
+                "{}: {} triangles, {} -> {} vertices, ACMR {:.3} -> {:.3}",
+                m.name, after.triangle_count, before.vertex_count, after.vertex_count, before.acmr, after.acmr,
+            );
+
+            let lods = mesh_simplifier::generate_lods(&vertices, &indices);
+            log::info!(
+                "{}: LOD triangle counts {:?}",
+                m.name, lods.iter().map(|lod| lod.len() / 3).collect::<Vec<_>>(),
+            );
+
+            Ok(MeshData {
+                name: m.name,
+                vertices,
+                lods,
                 material: m.mesh.material_id.unwrap_or(0),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(ModelData { meshes, materials })
+}
+
+// Creates the GPU resources for data, must run on the thread owning the device
+pub fn upload_model(
+    data: ModelData,
+    file_path: &std::path::Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    vertex_layout: model::ModelVertexLayout,
+    assets: &mut AssetManager,
+) -> anyhow::Result<model::Model> {
+    let mut materials: Vec<model::Material> = Vec::new();
+    for m in data.materials {
+        // Materials that share an image share one texture
+        let diffuse_texture = match assets.add_texture_data(
+            &file_path.join(&m.diffuse_texture),
+            &m.diffuse_data,
+            m.diffuse_sampler,
+            device,
+            queue,
+        ) {
+            Ok(handle) => handle,
+            Err(e) => {
+                // No model owns the references taken so far, give them back
+                for material in &materials {
+                    assets.release_texture(material.diffuse_texture);
+                }
+                return Err(e);
             }
+        };
+        let texture = assets.texture(diffuse_texture).unwrap();
+        materials.push(model::Material::new(device, &m.name, diffuse_texture, texture, layout));
+    }
+
+    let meshes = data.meshes
+        .iter()
+        .map(|m| {
+            model::Mesh::with_lods(
+                device,
+                &m.name,
+                &m.vertices,
+                &m.lods,
+                m.material,
+                vertex_layout,
+            )
         })
         .collect::<Vec<_>>();
 
     Ok(model::Model { meshes, materials })
 }
 
+// Reads a skybox, either one equirectangular panorama or six face images in
+// +X, -X, +Y, -Y, +Z, -Z order, and returns its six faces
+pub async fn read_cubemap(
+    files: &[std::path::PathBuf],
+    progress: &LoadProgress,
+) -> anyhow::Result<Vec<image::DynamicImage>> {
+    let mut images = Vec::new();
+    for file in files {
+        images.push(texture::load_image(&progress.read(file).await?)?);
+    }
+
+    match images.len() {
+        1 => {
+            // A panorama spans 360 degrees, so a face covers a quarter of its width
+            let face_size = (images[0].width() / 4).max(1);
+            cubemap::equirectangular_to_faces(&images[0], face_size)
+        }
+        cubemap::FACE_COUNT => {
+            cubemap::check_faces(&images)?;
+            Ok(images)
+        }
+        count => anyhow::bail!("A skybox is one panorama or {} faces, got {} files", cubemap::FACE_COUNT, count),
+    }
+}
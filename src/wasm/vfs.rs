@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::state::hot_reload;
+
+// A source of asset files. Paths are always relative to the mount root and
+// use forward slashes, e.g. "models/cube/cube.obj".
+pub enum Mount {
+    #[cfg(not(target_arch = "wasm32"))]
+    Directory(PathBuf),
+    Memory(HashMap<String, Arc<Vec<u8>>>),
+}
+
+impl Mount {
+    fn read(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Mount::Directory(root) => {
+                let path = root.join(key);
+                if path.is_file() {
+                    Ok(Some(std::fs::read(path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            Mount::Memory(files) => Ok(files.get(key).map(|data| data.to_vec())),
+        }
+    }
+}
+
+// Mounts are searched last to first, so later mounts override earlier ones.
+// Files embedded with the embedded-assets feature come next, the default
+// locations (see default_read) are searched after all of them.
+static MOUNTS: RwLock<Vec<Arc<Mount>>> = RwLock::new(Vec::new());
+
+pub fn normalize(path: &Path) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+pub fn mount(mount: Mount) {
+    MOUNTS.write().unwrap().push(Arc::new(mount));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn mount_directory<P: Into<PathBuf>>(dir: P) {
+    mount(Mount::Directory(dir.into()));
+}
+
+pub fn mount_memory<I: IntoIterator<Item = (String, Vec<u8>)>>(files: I) -> usize {
+    let files = files
+        .into_iter()
+        .map(|(path, data)| (normalize(Path::new(&path)), Arc::new(data)))
+        .collect::<HashMap<_, _>>();
+    let count = files.len();
+    mount(Mount::Memory(files));
+    count
+}
+
+// Unpacks the whole archive into a memory mount, returns the number of files
+pub fn mount_zip(bytes: &[u8]) -> anyhow::Result<usize> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut files = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = match file.enclosed_name() {
+            Some(path) if file.is_file() => normalize(path),
+            _ => continue,
+        };
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        files.push((path, data));
+    }
+    Ok(mount_memory(files))
+}
+
+// Uncompressed ustar/GNU tar. Only regular files are mounted.
+pub fn mount_tar(bytes: &[u8]) -> anyhow::Result<usize> {
+    const BLOCK: usize = 512;
+
+    fn field(header: &[u8], range: std::ops::Range<usize>) -> String {
+        let raw = &header[range];
+        let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+        String::from_utf8_lossy(&raw[..end]).into_owned()
+    }
+
+    let mut files = Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    while offset + BLOCK <= bytes.len() {
+        let header = &bytes[offset..offset + BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let size_field = field(header, 124..136);
+        let size = usize::from_str_radix(size_field.trim(), 8)
+            .map_err(|_| anyhow::anyhow!("Invalid tar entry size {:?}", size_field))?;
+        let data_start = offset + BLOCK;
+        let data_end = data_start + size;
+        if data_end > bytes.len() {
+            anyhow::bail!("Tar archive is truncated");
+        }
+        let data = &bytes[data_start..data_end];
+
+        match header[156] {
+            // GNU long name, applies to the next entry
+            b'L' => long_name = Some(field(data, 0..data.len())),
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let prefix = field(header, 345..500);
+                    let name = field(header, 0..100);
+                    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                });
+                files.push((name, data.to_vec()));
+            }
+            _ => long_name = None,
+        }
+
+        offset = data_start + size.div_ceil(BLOCK) * BLOCK;
+    }
+
+    Ok(mount_memory(files))
+}
+
+// Fetches a .zip or .tar pack in one request and serves its files from memory
+pub async fn mount_pack(path: &Path) -> anyhow::Result<usize> {
+    // Packs given on the command line are relative to the working directory
+    #[cfg(not(target_arch = "wasm32"))]
+    let bytes = if path.is_file() { std::fs::read(path)? } else { read(path).await? };
+    #[cfg(target_arch = "wasm32")]
+    let bytes = read(path).await?;
+
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("zip") => mount_zip(&bytes),
+        Some("tar") => mount_tar(&bytes),
+        _ => anyhow::bail!("{:?} isn't a .zip or .tar pack", path),
+    }
+}
+
+static STARTUP_MOUNTED: AtomicBool = AtomicBool::new(false);
+
+// Mounts what was given at startup. On native builds that's the --assets <dir>
+// directories, the first one given wins, followed by the --pack <file> packs
+// which override them. On the web it's the RES_PACK file (relative to
+// RES_PATH) set at compile time. Only the first call does anything, every
+// State created afterwards shares the mounts.
+pub async fn mount_startup_assets() -> anyhow::Result<()> {
+    if STARTUP_MOUNTED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut dirs = Vec::new();
+        let mut packs = Vec::new();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if let Some(dir) = arg.strip_prefix("--assets=") {
+                dirs.push(dir.to_string());
+            } else if let Some(pack) = arg.strip_prefix("--pack=") {
+                packs.push(pack.to_string());
+            } else if arg == "--assets" {
+                dirs.extend(args.next());
+            } else if arg == "--pack" {
+                packs.extend(args.next());
+            }
+        }
+
+        for dir in dirs.into_iter().rev() {
+            log::info!("Mounted directory {}", dir);
+            mount_directory(dir);
+        }
+        for pack in packs {
+            let count = mount_pack(Path::new(&pack)).await?;
+            log::info!("Mounted {} files from {}", count, pack);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    if let Some(pack) = option_env!("RES_PACK") {
+        let count = mount_pack(Path::new(pack)).await?;
+        log::info!("Mounted {} files from {}", count, pack);
+    }
+
+    Ok(())
+}
+
+pub async fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let key = normalize(path);
+    let mounts = MOUNTS.read().unwrap().clone();
+    for mount in mounts.iter().rev() {
+        if let Some(data) = mount.read(&key)? {
+            return Ok(data);
+        }
+    }
+
+    #[cfg(feature = "embedded-assets")]
+    if !hot_reload_active() {
+        if let Some(data) = super::embedded::get(&key) {
+            return Ok(data.to_vec());
+        }
+    }
+
+    default_read(&key).await
+}
+
+// In hot reload mode the source tree has to win over the compiled in copy
+#[cfg(feature = "embedded-assets")]
+fn hot_reload_active() -> bool {
+    #[cfg(not(target_arch = "wasm32"))]
+    return hot_reload::enabled();
+    #[cfg(target_arch = "wasm32")]
+    return false;
+}
+
+#[cfg(target_arch = "wasm32")]
+fn format_url(file_name: &str) -> reqwest::Url {
+    let window = web_sys::window().unwrap();
+    let location = window.location();
+    let base = reqwest::Url::parse(&format!(
+        "{}/{}/",
+        location.origin().unwrap(),
+        option_env!("RES_PATH").unwrap_or("data"),
+    )).unwrap();
+    base.join(file_name).unwrap()
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn default_read(key: &str) -> anyhow::Result<Vec<u8>> {
+    let url = format_url(key);
+    let response = reqwest::get(url.clone()).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("Request for {} failed with {}", url, response.status());
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+// Directories searched for assets on native builds after the mounts, in
+// order: the ASSET_PATH environment variable (a list like PATH), the source
+// tree in hot reload mode, a data directory next to the executable and
+// finally the copy build.rs made at compile time.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn asset_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+
+    if let Some(paths) = std::env::var_os("ASSET_PATH") {
+        roots.extend(std::env::split_paths(&paths));
+    }
+
+    if hot_reload::enabled() {
+        roots.push(hot_reload::source_data_dir());
+    }
+
+    if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.to_path_buf())) {
+        roots.push(exe_dir.join("data"));
+    }
+
+    roots.push(Path::new(env!("OUT_DIR")).join("data"));
+    roots
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn find_asset(relative: &Path) -> anyhow::Result<PathBuf> {
+    let roots = asset_roots();
+
+    roots
+        .iter()
+        .map(|root| root.join(relative))
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow::anyhow!(
+            "{:?} not found, looked in:\n{}",
+            relative,
+            roots.iter().map(|root| format!("    {:?}", root)).collect::<Vec<_>>().join("\n"),
+        ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn default_read(key: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(std::fs::read(find_asset(Path::new(key))?)?)
+}
@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use webgpu_tut_lib::golden::{self, Outcome, Tolerance};
+
+// Renders every golden scene and compares it with tests/golden/<name>.png.
+// Run with UPDATE_GOLDEN=1 to accept intended changes.
+#[test]
+fn golden_images() {
+    let reference_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let output_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let tolerance = Tolerance::default();
+    // GPU mipmaps differ between adapters, and GL can't render them at all
+    std::env::set_var("MIPMAPS", "cpu");
+
+    let mut failures = Vec::new();
+    for scene in golden::scenes() {
+        match golden::check(&scene, &reference_dir, &output_dir, &tolerance) {
+            Ok(Outcome::Skipped) => eprintln!("No adapter available, skipping {}", scene.name),
+            Ok(Outcome::Matched) => {}
+            Ok(Outcome::Recorded(path)) => eprintln!("Recorded reference {:?}", path),
+            Err(e) => failures.push(format!("{}: {:?}", scene.name, e)),
+        }
+    }
+    assert!(failures.is_empty(), "Golden images differ:\n{}", failures.join("\n"));
+}